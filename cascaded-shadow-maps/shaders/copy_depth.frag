@@ -0,0 +1,12 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform texture2D static_depth;
+layout(set = 0, binding = 1) uniform sampler point_sampler;
+
+// Depth textures can't be the destination of a texture copy, so copy the
+// cached static depth into the shadow map by writing it out per fragment.
+void main() {
+    gl_FragDepth = texelFetch(
+        sampler2D(static_depth, point_sampler), ivec2(gl_FragCoord.xy), 0
+    ).r;
+}
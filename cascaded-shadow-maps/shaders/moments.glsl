@@ -0,0 +1,63 @@
+// Shared between the moments filtering passes and the shadow sampling code.
+
+const uint SHADOW_MODE_DEPTH_COMPARE = 0;
+const uint SHADOW_MODE_VARIANCE = 1;
+const uint SHADOW_MODE_EXPONENTIAL_VARIANCE = 2;
+
+// These need to be fairly low as the moments are stored in 16-bit floats and
+// the squared moments overflow past ~5.5.
+const float EVSM_POSITIVE_EXPONENT = 5.0;
+const float EVSM_NEGATIVE_EXPONENT = 5.0;
+
+// Warp a depth in [0, 1] into the two exponential depths used by EVSM.
+vec2 exponential_warp(float depth) {
+    depth = depth * 2.0 - 1.0;
+    float positive = exp(EVSM_POSITIVE_EXPONENT * depth);
+    float negative = -exp(-EVSM_NEGATIVE_EXPONENT * depth);
+    return vec2(positive, negative);
+}
+
+vec4 compute_moments(float depth, uint mode) {
+    if (mode == SHADOW_MODE_EXPONENTIAL_VARIANCE) {
+        vec2 warped = exponential_warp(depth);
+        return vec4(warped.x, warped.x * warped.x, warped.y, warped.y * warped.y);
+    }
+
+    return vec4(depth, depth * depth, 0.0, 0.0);
+}
+
+float linstep(float low, float high, float value) {
+    return clamp((value - low) / (high - low), 0.0, 1.0);
+}
+
+// https://developer.nvidia.com/gpugems/gpugems3/part-ii-light-and-shadows/chapter-8-summed-area-variance-shadow-maps
+float chebyshev_upper_bound(vec2 moments, float depth, float min_variance, float light_bleeding_reduction) {
+    if (depth <= moments.x) {
+        return 1.0;
+    }
+
+    float variance = max(moments.y - moments.x * moments.x, min_variance);
+    float distance = depth - moments.x;
+    float p_max = variance / (variance + distance * distance);
+
+    // Cut off the tail of the distribution to reduce light bleeding.
+    return linstep(light_bleeding_reduction, 1.0, p_max);
+}
+
+float moments_visibility(vec4 moments, float depth, uint mode, float light_bleeding_reduction) {
+    if (mode == SHADOW_MODE_EXPONENTIAL_VARIANCE) {
+        vec2 warped = exponential_warp(depth);
+        // The minimum variance needs to be scaled by the derivative of the warp.
+        float positive_min_variance = 0.0001 * EVSM_POSITIVE_EXPONENT * warped.x;
+        float negative_min_variance = 0.0001 * EVSM_NEGATIVE_EXPONENT * warped.y;
+        float positive = chebyshev_upper_bound(
+            moments.xy, warped.x, positive_min_variance * positive_min_variance, light_bleeding_reduction
+        );
+        float negative = chebyshev_upper_bound(
+            moments.zw, warped.y, negative_min_variance * negative_min_variance, light_bleeding_reduction
+        );
+        return min(positive, negative);
+    }
+
+    return chebyshev_upper_bound(moments.xy, depth, 0.00002, light_bleeding_reduction);
+}
@@ -1,17 +1,111 @@
 use ultraviolet::{Mat4, Vec3, Vec4};
+use wgpu::util::DeviceExt;
 
 pub struct CascadedShadowMaps {
-    textures: [wgpu::TextureView; 3],
+    textures: ShadowTextures,
     light_projection_buffers: [wgpu::Buffer; 3],
     light_projection_bind_groups: [wgpu::BindGroup; 3],
     projection_bgl: wgpu::BindGroupLayout,
+    bindings: TextureBindings,
+    filter_settings: FilterSettings,
+    horizontal_filter_pipeline: wgpu::ComputePipeline,
+    vertical_filter_pipeline: wgpu::ComputePipeline,
+    copy_static_depth_pipeline: wgpu::RenderPipeline,
+    scene_bounds: Option<BoundingBox>,
+    cascade_extents: [f32; 3],
+    matrices: [Mat4; 3],
+    /// The matrices that the static depth of each cascade was last rendered with.
+    static_matrices: [Option<Mat4>; 3],
+}
+
+/// An axis-aligned bounding box in world space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: impl Iterator<Item = Vec3>) -> Self {
+        points.fold(
+            Self::new(Vec3::broadcast(f32::MAX), Vec3::broadcast(f32::MIN)),
+            |bounds, point| {
+                Self::new(
+                    bounds.min.min_by_component(point),
+                    bounds.max.max_by_component(point),
+                )
+            },
+        )
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self::new(
+            self.min.min_by_component(other.min),
+            self.max.max_by_component(other.max),
+        )
+    }
+
+    pub fn corners(self) -> [Vec3; 8] {
+        let Self { min, max } = self;
+
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    fn transformed(self, matrix: Mat4) -> Self {
+        Self::from_points(
+            self.corners()
+                .iter()
+                .map(|&corner| matrix.transform_point3(corner)),
+        )
+    }
+}
+
+/// The layouts, buffers and samplers that the size-dependent textures are bound with.
+struct TextureBindings {
     rendering_bgl: wgpu::BindGroupLayout,
+    filter_bgl: wgpu::BindGroupLayout,
+    copy_static_depth_bgl: wgpu::BindGroupLayout,
     uniform_buffer: wgpu::Buffer,
+    filter_uniform_buffer: wgpu::Buffer,
+    comparison_sampler: wgpu::Sampler,
+    moments_sampler: wgpu::Sampler,
+    point_sampler: wgpu::Sampler,
+    /// Bound in place of the moments when they aren't allocated.
+    placeholder_moments_view: wgpu::TextureView,
+}
+
+/// Everything that needs to be recreated when the shadow map resolution changes.
+struct ShadowTextures {
+    size: u32,
+    depth_views: [wgpu::TextureView; 3],
+    static_depth_views: [wgpu::TextureView; 3],
+    copy_static_depth_bind_groups: [wgpu::BindGroup; 3],
     rendering_bind_group: wgpu::BindGroup,
+    /// Only allocated for the shadow modes that sample the moments.
+    moments: Option<MomentsTextures>,
 }
 
-impl CascadedShadowMaps {
-    pub fn new(device: &wgpu::Device, size: u32) -> Self {
+/// The bind groups that blur the depth textures into the moments textures.
+struct MomentsTextures {
+    horizontal_filter_bind_group: wgpu::BindGroup,
+    vertical_filter_bind_group: wgpu::BindGroup,
+}
+
+impl ShadowTextures {
+    fn new(device: &wgpu::Device, size: u32, mode: ShadowMode, bindings: &TextureBindings) -> Self {
         let array_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("cascaded shadow map - shadow texture array"),
             size: wgpu::Extent3d {
@@ -26,6 +120,50 @@ impl CascadedShadowMaps {
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
         });
 
+        let static_array_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("cascaded shadow map - static shadow texture array"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: 3,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+
+        let moments_texture = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth: 3,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: MOMENTS_FORMAT,
+                usage: wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::SAMPLED,
+            })
+        };
+
+        // The intermediate and final moments texture views.
+        let moments_views = if mode.uses_moments() {
+            let intermediate_moments_texture =
+                moments_texture("cascaded shadow map - intermediate moments texture array");
+            let moments_texture = moments_texture("cascaded shadow map - moments texture array");
+
+            Some((
+                intermediate_moments_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                moments_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ))
+        } else {
+            None
+        };
+
         let texture_view = |label, i| {
             array_texture.create_view(&wgpu::TextureViewDescriptor {
                 label: Some(&format!("cascaded shadow map - {} texture", label)),
@@ -35,6 +173,186 @@ impl CascadedShadowMaps {
             })
         };
 
+        let static_texture_view = |label, i| {
+            static_array_texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some(&format!("cascaded shadow map - {} static texture", label)),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: i,
+                array_layer_count: Some(std::num::NonZeroU32::new(1).unwrap()),
+                ..Default::default()
+            })
+        };
+
+        let static_depth_views = [
+            static_texture_view("near", 0),
+            static_texture_view("middle", 1),
+            static_texture_view("far", 2),
+        ];
+
+        let copy_static_depth_bind_group = |label, view| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!(
+                    "cascaded shadow map - {} copy static depth bind group",
+                    label
+                )),
+                layout: &bindings.copy_static_depth_bgl,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&bindings.point_sampler),
+                    },
+                ],
+            })
+        };
+
+        let array_texture_view = array_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let moments_texture_view = match &moments_views {
+            Some((_, moments_texture_view)) => moments_texture_view,
+            None => &bindings.placeholder_moments_view,
+        };
+
+        let rendering_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("cascaded shadow map - rendering bind group"),
+            layout: &bindings.rendering_bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&array_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&bindings.comparison_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: bindings.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(moments_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&bindings.moments_sampler),
+                },
+            ],
+        });
+
+        let filter_bind_group = |label, input, output| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &bindings.filter_bgl,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&bindings.point_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(output),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: bindings.filter_uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        Self {
+            size,
+            depth_views: [
+                texture_view("near", 0),
+                texture_view("middle", 1),
+                texture_view("far", 2),
+            ],
+            copy_static_depth_bind_groups: [
+                copy_static_depth_bind_group("near", &static_depth_views[0]),
+                copy_static_depth_bind_group("middle", &static_depth_views[1]),
+                copy_static_depth_bind_group("far", &static_depth_views[2]),
+            ],
+            static_depth_views,
+            rendering_bind_group,
+            moments: moments_views.as_ref().map(
+                |(intermediate_moments_texture_view, moments_texture_view)| MomentsTextures {
+                    horizontal_filter_bind_group: filter_bind_group(
+                        "cascaded shadow map - horizontal filter bind group",
+                        &array_texture_view,
+                        intermediate_moments_texture_view,
+                    ),
+                    vertical_filter_bind_group: filter_bind_group(
+                        "cascaded shadow map - vertical filter bind group",
+                        intermediate_moments_texture_view,
+                        moments_texture_view,
+                    ),
+                },
+            ),
+        }
+    }
+}
+
+/// How the shadow maps are sampled when rendering.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShadowMode {
+    /// Percentage-closer filtering of the depth textures with a comparison sampler.
+    DepthCompare,
+    /// Variance shadow maps. The depth and depth squared are blurred and sampled
+    /// with Chebyshev's inequality.
+    Variance,
+    /// Exponential variance shadow maps. Like variance shadow maps, but with
+    /// positively and negatively warped depths to reduce light bleeding.
+    ExponentialVariance,
+}
+
+impl ShadowMode {
+    pub fn iter() -> impl Iterator<Item = Self> {
+        [
+            Self::DepthCompare,
+            Self::Variance,
+            Self::ExponentialVariance,
+        ]
+        .iter()
+        .cloned()
+    }
+
+    fn uses_moments(self) -> bool {
+        self != Self::DepthCompare
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FilterSettings {
+    pub mode: ShadowMode,
+    /// The radius in texels of the separable box blur applied to the moments.
+    pub blur_radius: u32,
+    /// The fraction of the Chebyshev upper bound to cut off when sampling the
+    /// moments. Higher values reduce light bleeding but darken penumbras.
+    pub light_bleeding_reduction: f32,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        Self {
+            mode: ShadowMode::DepthCompare,
+            blur_radius: 2,
+            light_bleeding_reduction: 0.2,
+        }
+    }
+}
+
+const MOMENTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const FILTER_WORKGROUP_SIZE: u32 = 8;
+
+impl CascadedShadowMaps {
+    pub fn new(device: &wgpu::Device, size: u32) -> Self {
         let projection_buffer = |label| {
             device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some(&format!(
@@ -99,9 +417,99 @@ impl CascadedShadowMaps {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let filter_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cascaded shadow map - filter bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: MOMENTS_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
+        let copy_static_depth_bgl =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("cascaded shadow map - copy static depth bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: false,
+                            filtering: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("cascaded shadow map - uniform buffer"),
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
@@ -109,9 +517,15 @@ impl CascadedShadowMaps {
             mapped_at_creation: false,
         });
 
-        let array_texture_view = array_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let filter_settings = FilterSettings::default();
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let filter_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cascaded shadow map - filter uniform buffer"),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            contents: bytemuck::bytes_of(&FilterUniform::new(filter_settings)),
+        });
+
+        let comparison_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("cascaded shadow map - comparison sampler"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -122,24 +536,117 @@ impl CascadedShadowMaps {
             ..Default::default()
         });
 
-        let rendering_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("cascaded shadow map - rendering bind group"),
-            layout: &rendering_bgl,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&array_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+        let moments_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("cascaded shadow map - moments sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let point_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("cascaded shadow map - point sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let placeholder_moments_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("cascaded shadow map - placeholder moments texture array"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MOMENTS_FORMAT,
+            usage: wgpu::TextureUsage::SAMPLED,
+        });
+
+        let bindings = TextureBindings {
+            rendering_bgl,
+            filter_bgl,
+            copy_static_depth_bgl,
+            uniform_buffer,
+            filter_uniform_buffer,
+            comparison_sampler,
+            moments_sampler,
+            point_sampler,
+            placeholder_moments_view: placeholder_moments_texture.create_view(
+                &wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2Array),
+                    ..Default::default()
                 },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: uniform_buffer.as_entire_binding(),
+            ),
+        };
+
+        let filter_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("cascaded shadow map - filter pipeline layout"),
+                bind_group_layouts: &[&bindings.filter_bgl],
+                push_constant_ranges: &[],
+            });
+
+        let filter_pipeline = |label, module| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&filter_pipeline_layout),
+                module,
+                entry_point: "main",
+            })
+        };
+
+        let cs_moments_horizontal = device.create_shader_module(&wgpu::include_spirv!(
+            "../shaders/compiled/moments_horizontal.comp.spv"
+        ));
+        let cs_moments_vertical = device.create_shader_module(&wgpu::include_spirv!(
+            "../shaders/compiled/moments_vertical.comp.spv"
+        ));
+
+        let copy_static_depth_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("cascaded shadow map - copy static depth pipeline layout"),
+                bind_group_layouts: &[&bindings.copy_static_depth_bgl],
+                push_constant_ranges: &[],
+            });
+
+        let vs_copy_depth = device.create_shader_module(&wgpu::include_spirv!(
+            "../shaders/compiled/copy_depth.vert.spv"
+        ));
+        let fs_copy_depth = device.create_shader_module(&wgpu::include_spirv!(
+            "../shaders/compiled/copy_depth.frag.spv"
+        ));
+
+        let copy_static_depth_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("cascaded shadow map - copy static depth pipeline"),
+                layout: Some(&copy_static_depth_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vs_copy_depth,
+                    entry_point: "main",
+                    buffers: &[],
                 },
-            ],
-        });
+                fragment: Some(wgpu::FragmentState {
+                    module: &fs_copy_depth,
+                    entry_point: "main",
+                    targets: &[],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                    clamp_depth: false,
+                }),
+                multisample: wgpu::MultisampleState::default(),
+            });
 
         let projection_bind_group = |label, i: usize| {
             device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -156,11 +663,7 @@ impl CascadedShadowMaps {
         };
 
         Self {
-            textures: [
-                texture_view("near", 0),
-                texture_view("middle", 1),
-                texture_view("far", 2),
-            ],
+            textures: ShadowTextures::new(device, size, filter_settings.mode, &bindings),
             light_projection_bind_groups: [
                 projection_bind_group("near", 0),
                 projection_bind_group("middle", 1),
@@ -168,14 +671,91 @@ impl CascadedShadowMaps {
             ],
             light_projection_buffers: projection_buffers,
             projection_bgl,
-            uniform_buffer,
-            rendering_bgl,
-            rendering_bind_group,
+            bindings,
+            filter_settings,
+            horizontal_filter_pipeline: filter_pipeline(
+                "cascaded shadow map - horizontal filter pipeline",
+                &cs_moments_horizontal,
+            ),
+            vertical_filter_pipeline: filter_pipeline(
+                "cascaded shadow map - vertical filter pipeline",
+                &cs_moments_vertical,
+            ),
+            copy_static_depth_pipeline,
+            scene_bounds: None,
+            cascade_extents: [0.0; 3],
+            matrices: [Mat4::identity(); 3],
+            static_matrices: [None; 3],
         }
     }
 
+    pub fn size(&self) -> u32 {
+        self.textures.size
+    }
+
+    /// Recreate the shadow textures at a new resolution. Returns whether they
+    /// were recreated, in which case any bind groups previously returned by
+    /// `rendering_bind_group` or views returned by `textures` should no longer
+    /// be used.
+    pub fn resize(&mut self, device: &wgpu::Device, size: u32) -> bool {
+        if size == self.textures.size {
+            return false;
+        }
+
+        self.recreate_textures(device, size);
+        true
+    }
+
+    fn recreate_textures(&mut self, device: &wgpu::Device, size: u32) {
+        self.textures =
+            ShadowTextures::new(device, size, self.filter_settings.mode, &self.bindings);
+        self.invalidate_static_depth();
+    }
+
     pub fn textures(&self) -> &[wgpu::TextureView; 3] {
-        &self.textures
+        &self.textures.depth_views
+    }
+
+    /// The depth textures that static shadow casters, such as the terrain, are
+    /// cached in. Only render into these for the cascades returned by
+    /// `static_cascades_to_render`.
+    pub fn static_textures(&self) -> &[wgpu::TextureView; 3] {
+        &self.textures.static_depth_views
+    }
+
+    /// Which cascades need their static depth re-rendered because their matrix
+    /// has changed since it was last cached. The caller is expected to render
+    /// the static casters into `static_textures` for each of these cascades
+    /// before calling `copy_static_depth`.
+    pub fn static_cascades_to_render(&mut self) -> [bool; 3] {
+        let mut to_render = [false; 3];
+
+        for i in 0..3 {
+            if self.static_matrices[i] != Some(self.matrices[i]) {
+                self.static_matrices[i] = Some(self.matrices[i]);
+                to_render[i] = true;
+            }
+        }
+
+        to_render
+    }
+
+    /// Force the static depth of every cascade to be re-rendered, for when the
+    /// static shadow casters themselves have changed.
+    pub fn invalidate_static_depth(&mut self) {
+        self.static_matrices = [None; 3];
+    }
+
+    /// Overwrite the depth of a cascade's shadow pass with its cached static
+    /// depth. Dynamic shadow casters can then be drawn on top.
+    pub fn copy_static_depth<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, cascade: usize) {
+        render_pass.set_pipeline(&self.copy_static_depth_pipeline);
+        render_pass.set_bind_group(
+            0,
+            &self.textures.copy_static_depth_bind_groups[cascade],
+            &[],
+        );
+        render_pass.draw(0..3, 0..1);
     }
 
     pub fn light_projection_bind_groups(&self) -> &[wgpu::BindGroup; 3] {
@@ -187,23 +767,121 @@ impl CascadedShadowMaps {
     }
 
     pub fn rendering_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
-        &self.rendering_bgl
+        &self.bindings.rendering_bgl
     }
 
     pub fn rendering_bind_group(&self) -> &wgpu::BindGroup {
-        &self.rendering_bind_group
+        &self.textures.rendering_bind_group
+    }
+
+    pub fn filter_settings(&self) -> FilterSettings {
+        self.filter_settings
+    }
+
+    /// Set how the shadow maps are filtered and sampled. The moments textures
+    /// are only allocated, and generated by `filter`, when the mode isn't
+    /// `ShadowMode::DepthCompare`. Switching between the two recreates the
+    /// textures, the same as `resize`, and returns whether it did.
+    pub fn set_filter_settings(
+        &mut self,
+        filter_settings: FilterSettings,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> bool {
+        let uses_moments = filter_settings.mode.uses_moments();
+        self.filter_settings = filter_settings;
+
+        let recreate = uses_moments != self.textures.moments.is_some();
+
+        if recreate {
+            self.recreate_textures(device, self.textures.size);
+        }
+
+        queue.write_buffer(
+            &self.bindings.filter_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&FilterUniform::new(filter_settings)),
+        );
+
+        let sampling = SamplingUniform::new(filter_settings);
+
+        queue.write_buffer(
+            &self.bindings.uniform_buffer,
+            SamplingUniform::OFFSET,
+            bytemuck::bytes_of(&sampling),
+        );
+
+        recreate
+    }
+
+    /// Convert the rendered depth textures into blurred moments. This needs to
+    /// be recorded after the shadow passes and before anything samples the shadows.
+    pub fn filter(&self, encoder: &mut wgpu::CommandEncoder) {
+        let moments = match &self.textures.moments {
+            Some(moments) => moments,
+            None => return,
+        };
+
+        let size = self.textures.size;
+        let mut dispatch_size = size / FILTER_WORKGROUP_SIZE;
+        let rem = size % FILTER_WORKGROUP_SIZE;
+        if rem != 0 {
+            dispatch_size += 1;
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("cascaded shadow map - filter pass"),
+        });
+
+        compute_pass.set_pipeline(&self.horizontal_filter_pipeline);
+        compute_pass.set_bind_group(0, &moments.horizontal_filter_bind_group, &[]);
+        compute_pass.dispatch(dispatch_size, dispatch_size, 3);
+
+        compute_pass.set_pipeline(&self.vertical_filter_pipeline);
+        compute_pass.set_bind_group(0, &moments.vertical_filter_bind_group, &[]);
+        compute_pass.dispatch(dispatch_size, dispatch_size, 3);
+    }
+
+    /// Fit the cascades tightly against the bounds of everything that can cast
+    /// or receive shadows, instead of a bounding sphere around each cascade.
+    /// This gives much better depth precision and texel density, at the cost of
+    /// the shadows shimmering slightly as the camera moves. `None` goes back to
+    /// the bounding sphere fitting. Takes effect on the next `update_params`.
+    pub fn set_scene_bounds(&mut self, scene_bounds: Option<BoundingBox>) {
+        self.scene_bounds = scene_bounds;
+    }
+
+    /// The number of shadow map texels per world unit for each cascade, as of
+    /// the last call to `update_params`.
+    pub fn texel_densities(&self) -> [f32; 3] {
+        let size = self.textures.size as f32;
+        let density = |extent: f32| size / extent;
+
+        [
+            density(self.cascade_extents[0]),
+            density(self.cascade_extents[1]),
+            density(self.cascade_extents[2]),
+        ]
     }
 
     pub fn update_params(
-        &self,
+        &mut self,
         camera: CameraParams,
         cascade_splits: [f32; 4],
         origin_to_light: Vec3,
         queue: &wgpu::Queue,
     ) {
-        let (uniform, matrices) = update_cascades(camera, cascade_splits, origin_to_light);
+        let (mut uniform, matrices, extents) =
+            update_cascades(camera, cascade_splits, origin_to_light, self.scene_bounds);
+        uniform.sampling = SamplingUniform::new(self.filter_settings);
+        self.cascade_extents = extents;
+        self.matrices = matrices;
 
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(
+            &self.bindings.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&uniform),
+        );
 
         for i in 0..3 {
             queue.write_buffer(
@@ -227,6 +905,42 @@ pub struct CameraParams {
 struct Uniform {
     matrices: [Mat4; 3],
     split_depths: [f32; 2],
+    sampling: SamplingUniform,
+}
+
+/// The tail of `Uniform` that can be updated independently of the matrices.
+#[repr(C)]
+#[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct SamplingUniform {
+    mode: u32,
+    light_bleeding_reduction: f32,
+}
+
+impl SamplingUniform {
+    const OFFSET: u64 = (std::mem::size_of::<Mat4>() * 3 + std::mem::size_of::<[f32; 2]>()) as u64;
+
+    fn new(settings: FilterSettings) -> Self {
+        Self {
+            mode: settings.mode as u32,
+            light_bleeding_reduction: settings.light_bleeding_reduction,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterUniform {
+    mode: u32,
+    blur_radius: u32,
+}
+
+impl FilterUniform {
+    fn new(settings: FilterSettings) -> Self {
+        Self {
+            mode: settings.mode as u32,
+            blur_radius: settings.blur_radius,
+        }
+    }
 }
 
 /// Calculate split depths based on view camera frustum
@@ -260,7 +974,8 @@ fn update_cascades(
     camera: CameraParams,
     cascade_splits: [f32; 4],
     origin_to_light: Vec3,
-) -> (Uniform, [Mat4; 3]) {
+    scene_bounds: Option<BoundingBox>,
+) -> (Uniform, [Mat4; 3], [f32; 3]) {
     let clip_range = camera.far_clip - camera.near_clip;
 
     let inverse_camera_projection_view = camera.projection_view.inversed();
@@ -295,6 +1010,14 @@ fn update_cascades(
             frustum_corners[i] = frustum_corners[i] + (dist * last_split_dist);
         }
 
+        let light_dir = -origin_to_light;
+
+        if let Some(scene_bounds) = scene_bounds {
+            let (matrix, extent) = fit_to_scene_bounds(&frustum_corners, light_dir, scene_bounds);
+            let split_depth = (camera.near_clip + split_dist * clip_range) * -1.0;
+            return (matrix, split_depth, extent);
+        }
+
         let mut frustum_center = Vec3::zero();
         for i in 0..8 {
             frustum_center += frustum_corners[i];
@@ -311,8 +1034,6 @@ fn update_cascades(
         let max_extents = Vec3::broadcast(radius);
         let min_extents = -max_extents;
 
-        let light_dir = -origin_to_light;
-
         let light_view_matrix = Mat4::look_at(
             frustum_center - light_dir * -min_extents.z,
             frustum_center,
@@ -330,12 +1051,14 @@ fn update_cascades(
         let matrix = light_ortho_matrix * light_view_matrix;
         let split_depth = (camera.near_clip + split_dist * clip_range) * -1.0;
 
-        (matrix, split_depth)
+        (matrix, split_depth, radius * 2.0)
     };
 
-    let (matrix_1, split_depth_1) = calculate_matrix(cascade_splits[0], cascade_splits[1]);
-    let (matrix_2, split_depth_2) = calculate_matrix(cascade_splits[1], cascade_splits[2]);
-    let (matrix_3, _) = calculate_matrix(cascade_splits[2], cascade_splits[3]);
+    let (matrix_1, split_depth_1, extent_1) =
+        calculate_matrix(cascade_splits[0], cascade_splits[1]);
+    let (matrix_2, split_depth_2, extent_2) =
+        calculate_matrix(cascade_splits[1], cascade_splits[2]);
+    let (matrix_3, _, extent_3) = calculate_matrix(cascade_splits[2], cascade_splits[3]);
 
     let matrices = [matrix_1, matrix_2, matrix_3];
     // compensate for the Y-flip difference between the matrix and texture coordinates.
@@ -354,7 +1077,65 @@ fn update_cascades(
             // split depths as we can just sample the 3rd shadow texture even if an
             // object lies beyond it.
             split_depths: [split_depth_1, split_depth_2],
+            sampling: SamplingUniform::default(),
         },
         matrices,
+        [extent_1, extent_2, extent_3],
+    )
+}
+
+/// Calculate a light matrix that covers the part of the cascade's frustum that
+/// lies within the scene bounds, and the larger of its width and height.
+fn fit_to_scene_bounds(
+    frustum_corners: &[Vec3; 8],
+    light_dir: Vec3,
+    scene_bounds: BoundingBox,
+) -> (Mat4, f32) {
+    let frustum_center = frustum_corners
+        .iter()
+        .fold(Vec3::zero(), |sum, &corner| sum + corner)
+        / 8.0;
+
+    // As the projection is orthographic, we can look from the center of the
+    // frustum and use negative near planes.
+    let light_view_matrix =
+        Mat4::look_at(frustum_center, frustum_center + light_dir, Vec3::unit_y());
+
+    let frustum_bounds = BoundingBox::from_points(
+        frustum_corners
+            .iter()
+            .map(|&corner| light_view_matrix.transform_point3(corner)),
+    );
+    let scene_bounds = scene_bounds.transformed(light_view_matrix);
+
+    // Only the parts of the frustum that contain something are worth rendering.
+    let mut min = frustum_bounds.min.max_by_component(scene_bounds.min);
+    let mut max = frustum_bounds.max.min_by_component(scene_bounds.max);
+
+    if min.x >= max.x || min.y >= max.y {
+        // The frustum doesn't overlap the scene at all, so there's nothing to
+        // fit against.
+        min = frustum_bounds.min;
+        max = frustum_bounds.max;
+    }
+
+    // The light looks down -Z. Anything in the scene between the light and the
+    // frustum can cast shadows into it, so the near plane is always the scene's
+    // closest point, but nothing beyond the frustum or the scene matters.
+    let near = -scene_bounds.max.z;
+    let far = -frustum_bounds.min.z.max(scene_bounds.min.z);
+
+    let light_ortho_matrix = ultraviolet::projection::orthographic_wgpu_dx(
+        min.x,
+        max.x,
+        min.y,
+        max.y,
+        near,
+        far.max(near + f32::EPSILON),
+    );
+
+    (
+        light_ortho_matrix * light_view_matrix,
+        (max.x - min.x).max(max.y - min.y),
     )
 }
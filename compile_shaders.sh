@@ -19,3 +19,12 @@ output="shaders/compiled/${dir}_$(basename $file).spv"
 glslc $file -o $output
 spirv-opt $output -O -o $output
 done
+
+rm -r cascaded-shadow-maps/shaders/compiled/*.spv
+
+for file in cascaded-shadow-maps/shaders/*.{vert,frag,comp}
+do
+output=cascaded-shadow-maps/shaders/compiled/$(basename $file).spv
+glslc $file -o $output
+spirv-opt $output -O -o $output
+done
@@ -45,13 +45,35 @@ pub struct Camera {
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Settings {
-    pub base_colour: Vec3,
-    pub detail_map_scale: f32,
     pub ambient_lighting: Vec3,
-    pub roughness: f32,
     pub specular_factor: f32,
     pub mode: u32,
     pub ship_movement_bounds: f32,
+    /// The angle around the y axis that the wind blows towards, in radians.
+    pub wind_direction: f32,
+    pub wind_strength: f32,
+    /// Whether the ships flock together instead of flying around on their own.
+    pub flocking: u32,
+    pub boid_neighbour_radius: f32,
+    pub boid_separation_weight: f32,
+    pub boid_alignment_weight: f32,
+    pub boid_cohesion_weight: f32,
+    /// How far above the ground the ships fly.
+    pub ship_clearance: f32,
+    /// Whether the land craft steer around each other instead of driving
+    /// through each other.
+    pub land_craft_avoidance: u32,
+    /// Two land craft overlap when they're closer than twice this.
+    pub land_craft_radius: f32,
+}
+
+impl Settings {
+    /// The ships are sorted into a grid with at least 3 cells across the area
+    /// they move in, and only look for neighbours in the cells next to their
+    /// own, so the flocking radius can't be wider than a cell.
+    pub fn max_boid_neighbour_radius(&self) -> f32 {
+        self.ship_movement_bounds * 2.0 / 3.0
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -61,6 +83,7 @@ pub enum Mode {
     Noise,
     HueNoise,
     ShadowCascade,
+    TriplanarWeights,
 }
 
 impl Mode {
@@ -71,10 +94,138 @@ impl Mode {
             Self::Noise,
             Self::HueNoise,
             Self::ShadowCascade,
+            Self::TriplanarWeights,
         ])
     }
 }
 
+/// One of the materials that the terrain surface is blended from.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainLayer {
+    pub albedo: Vec3,
+    pub roughness: f32,
+    pub detail_scale: f32,
+    /// How strongly the layer's detail map bends the surface normal.
+    pub normal_strength: f32,
+    /// The slopes that the layer covers when blending by rules, in radians from flat.
+    pub slope_range: Vec2,
+    /// The heights that the layer covers when blending by rules, from 0 at the
+    /// lowest point of the terrain to 1 at the highest.
+    pub height_range: Vec2,
+    pub _padding: [u32; 2],
+}
+
+impl TerrainLayer {
+    fn new(
+        albedo: Vec3,
+        roughness: f32,
+        detail_scale: f32,
+        normal_strength: f32,
+        slope_range: Vec2,
+        height_range: Vec2,
+    ) -> Self {
+        Self {
+            albedo,
+            roughness,
+            detail_scale,
+            normal_strength,
+            slope_range,
+            height_range,
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub const NUM_TERRAIN_LAYERS: usize = 4;
+
+pub const TERRAIN_LAYER_NAMES: [&str; NUM_TERRAIN_LAYERS] =
+    ["Wind-Ripple Sand", "Packed Sand", "Rock", "Salt Flat"];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainMaterial {
+    pub layers: [TerrainLayer; NUM_TERRAIN_LAYERS],
+    pub blend_mode: u32,
+    /// How far past the edges of its slope and height ranges a layer fades out.
+    pub blend_softness: f32,
+    pub detail_projection: u32,
+    pub _end_padding: u32,
+}
+
+impl Default for TerrainMaterial {
+    fn default() -> Self {
+        Self {
+            layers: [
+                TerrainLayer::new(
+                    Vec3::new(0.8, 0.535, 0.297),
+                    0.207,
+                    1.5,
+                    1.0,
+                    Vec2::new(0.0, 0.35),
+                    Vec2::new(0.1, 1.0),
+                ),
+                TerrainLayer::new(
+                    Vec3::new(0.65, 0.43, 0.25),
+                    0.6,
+                    3.0,
+                    0.4,
+                    Vec2::new(0.0, 0.35),
+                    Vec2::new(0.0, 0.1),
+                ),
+                TerrainLayer::new(
+                    Vec3::new(0.35, 0.22, 0.15),
+                    0.8,
+                    0.75,
+                    1.0,
+                    Vec2::new(0.35, 1.6),
+                    Vec2::new(0.0, 1.0),
+                ),
+                TerrainLayer::new(
+                    Vec3::new(0.85, 0.83, 0.78),
+                    0.4,
+                    4.0,
+                    0.2,
+                    Vec2::new(0.0, 0.05),
+                    Vec2::new(0.0, 0.02),
+                ),
+            ],
+            blend_mode: TerrainBlendMode::Rules as u32,
+            blend_softness: 0.05,
+            detail_projection: DetailProjection::Triplanar as u32,
+            _end_padding: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum TerrainBlendMode {
+    /// Blend the layers by the slope and height of the ground.
+    Rules,
+    /// Blend the layers by the weights in the terrain's splat map.
+    SplatMap,
+}
+
+impl TerrainBlendMode {
+    pub fn iter() -> impl Iterator<Item = (Self, u32)> {
+        enumerate(&[Self::Rules, Self::SplatMap])
+    }
+}
+
+/// How the terrain's detail maps are wrapped over the surface.
+#[derive(Debug, Copy, Clone)]
+pub enum DetailProjection {
+    Uv,
+    /// Project along each world axis, which avoids stretching on steep slopes.
+    Triplanar,
+}
+
+impl DetailProjection {
+    pub fn iter() -> impl Iterator<Item = (Self, u32)> {
+        enumerate(&[Self::Uv, Self::Triplanar])
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct TonemapperSettings {
@@ -120,10 +271,16 @@ pub struct Ship {
     pub position: Vec3,
     pub y_rotation: f32,
     pub _rotation_matrix: [Vec4; 3],
+    /// In radians per second.
     pub rotation_speed: f32,
-    pub _end_padding: [u32; 3],
+    /// How far the ship is banked into its turn, in radians.
+    pub roll: f32,
+    pub _end_padding: [u32; 2],
 }
 
+/// The most places on a vehicle that one set of particles can be spawned from.
+pub const MAX_SPAWN_OFFSETS: usize = 4;
+
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ParticlesBufferInfo {
@@ -132,6 +289,34 @@ pub struct ParticlesBufferInfo {
     pub _particle_duration: f32,
     pub half_size_linear: f32,
     pub _last_particle_spawn_time: f32,
+    /// In seconds. Particles are spawned every step if this is 0.
+    pub spawn_cooldown: f32,
+    pub spawn_offsets: [Vec4; MAX_SPAWN_OFFSETS],
+    pub num_spawn_offsets: u32,
+    pub up_speed: f32,
+    pub random_speed: f32,
+    pub _end_padding: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VehicleTypeInfo {
+    /// UVs with a v above this are scrolled along u, such as for treads.
+    pub uv_scroll_threshold: f32,
+    /// In UVs per second.
+    pub uv_scroll_speed: f32,
+    /// How far flying vehicles bob around their clearance.
+    pub bob_amplitude: f32,
+    pub _end_padding: u32,
+}
+
+/// How the vehicles of one type are sorted into a grid to find their neighbours.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VehicleGrid {
+    /// Whether the grid wraps around, as flying vehicles do.
+    pub wraps: u32,
+    pub _end_padding: [u32; 3],
 }
 
 #[repr(C)]
@@ -140,9 +325,7 @@ pub struct Particle {
     pub position: Vec3,
     pub time_spawned: f32,
     pub velocity: Vec3,
-    pub _padding_0: u32,
-    pub _view_space_position: Vec3,
-    pub _padding_1: u32,
+    pub _padding: u32,
 }
 
 #[repr(C)]
@@ -150,6 +333,56 @@ pub struct Particle {
 pub struct Time {
     pub time_since_start: f32,
     pub delta_time: f32,
+    /// The number of simulation steps so far, which feeds the randomness in
+    /// the compute shaders.
+    pub frame: u32,
+}
+
+/// The area of the XZ plane that the terrain covers, along with the range of
+/// heights on it.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SceneBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+impl SceneBounds {
+    pub fn center(&self) -> Vec2 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Half of the larger of the width and depth.
+    pub fn half_size(&self) -> f32 {
+        let size = self.max - self.min;
+        size.x.max(size.y) / 2.0
+    }
+
+    /// How far from the center the ships can fly before wrapping around.
+    pub fn max_ship_movement_bounds(&self) -> f32 {
+        self.half_size() * 1.25
+    }
+}
+
+/// A square of the chunked terrain, drawn as an instance of the grid mesh for
+/// its level of detail.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainChunk {
+    pub origin: Vec2,
+    pub size: Vec2,
+    pub lod: u32,
+    /// The levels of detail of the chunks to the -x, +x, -z and +z sides, so
+    /// that the edges can be stitched to them.
+    pub neighbour_lods: [u32; 4],
+    /// The corner of the chunk's streamed heights in the tile atlas, for chunks
+    /// outside the scene bounds.
+    pub tile: [u32; 2],
+    /// The level of detail that the streamed heights were sampled at, which is
+    /// never coarser than `lod`.
+    pub tile_lod: u32,
 }
 
 #[repr(C)]
@@ -158,4 +391,35 @@ pub struct LandCraft {
     pub position: Vec3,
     pub facing: f32,
     pub _rotation_matrix: [Vec4; 3],
+    /// The index of the route that the craft drives around.
+    pub route: u32,
+    /// How far around the route the point that the craft is steering towards
+    /// is, in waypoints.
+    pub route_progress: f32,
+    pub speed: f32,
+    pub _padding: u32,
+}
+
+/// Where a route's waypoints are in the waypoints buffer.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Route {
+    pub start: u32,
+    pub len: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowDebugSettings {
+    pub depth_min: f32,
+    pub depth_max: f32,
+}
+
+impl Default for ShadowDebugSettings {
+    fn default() -> Self {
+        Self {
+            depth_min: 0.0,
+            depth_max: 1.0,
+        }
+    }
 }
@@ -1,9 +1,22 @@
 #version 450
 
 layout(location = 0) in float in_height;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec2 in_uv;
+layout(location = 3) in vec4 in_tangent;
 
 layout(location = 0) out float out_height;
+// World space normal in rgb and the angle of the slope in alpha.
+layout(location = 1) out vec4 out_normal;
+layout(location = 2) out vec2 out_uv;
+layout(location = 3) out vec4 out_tangent;
 
 void main() {
     out_height = in_height;
+
+    vec3 normal = normalize(in_normal);
+    out_normal = vec4(normal, acos(clamp(normal.y, -1.0, 1.0)));
+
+    out_uv = in_uv;
+    out_tangent = vec4(normalize(in_tangent.xyz), in_tangent.w);
 }
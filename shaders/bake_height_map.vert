@@ -1,16 +1,36 @@
 #version 450
 
+#include "includes/structs.glsl"
+
 layout(location = 0) in vec3 in_position;
 layout(location = 1) in vec3 in_normal;
 layout(location = 2) in vec2 in_uv;
 layout(location = 3) in vec4 in_tangent;
+// Where to move the terrain to for this sample of each texel, in clip space.
+layout(location = 4) in vec2 in_sample_offset;
 
 layout(location = 0) out float out_height;
+layout(location = 1) out vec3 out_normal;
+layout(location = 2) out vec2 out_uv;
+layout(location = 3) out vec4 out_tangent;
+
+layout(set = 0, binding = 6) uniform SceneBoundsUniform {
+    SceneBounds scene_bounds;
+};
 
 void main() {
     out_height = in_position.y;
+    out_normal = in_normal;
+    out_uv = in_uv;
+    out_tangent = in_tangent;
+
+    // Map the scene bounds onto the whole height map, with the minimum z at the top.
+    vec2 uv = (in_position.xz - scene_bounds.min) / (scene_bounds.max - scene_bounds.min);
+    vec2 position = uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0) + in_sample_offset;
 
-    vec2 position = in_position.xz * vec2(0.5, -0.5);
+    // Higher surfaces are closer, so that the depth test keeps the highest
+    // height wherever triangles overlap.
+    float depth = 1.0 - (in_position.y - scene_bounds.min_height) / max(scene_bounds.max_height - scene_bounds.min_height, 1e-5);
 
-    gl_Position = vec4(position, 0.0, 1.0);
+    gl_Position = vec4(position, depth, 1.0);
 }
@@ -0,0 +1,47 @@
+#version 450
+
+#include "includes/structs.glsl"
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+    Camera camera;
+};
+
+layout(set = 1, binding = 0) uniform LightProjectionUniform {
+    mat4 light_projection;
+};
+
+layout(location = 0) out vec4 colour;
+
+// The 12 edges of the light's clip space box, as pairs of line ends.
+const vec3 EDGES[24] = {
+    vec3(-1.0, -1.0, 0.0), vec3(1.0, -1.0, 0.0),
+    vec3(1.0, -1.0, 0.0), vec3(1.0, 1.0, 0.0),
+    vec3(1.0, 1.0, 0.0), vec3(-1.0, 1.0, 0.0),
+    vec3(-1.0, 1.0, 0.0), vec3(-1.0, -1.0, 0.0),
+
+    vec3(-1.0, -1.0, 1.0), vec3(1.0, -1.0, 1.0),
+    vec3(1.0, -1.0, 1.0), vec3(1.0, 1.0, 1.0),
+    vec3(1.0, 1.0, 1.0), vec3(-1.0, 1.0, 1.0),
+    vec3(-1.0, 1.0, 1.0), vec3(-1.0, -1.0, 1.0),
+
+    vec3(-1.0, -1.0, 0.0), vec3(-1.0, -1.0, 1.0),
+    vec3(1.0, -1.0, 0.0), vec3(1.0, -1.0, 1.0),
+    vec3(1.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0),
+    vec3(-1.0, 1.0, 0.0), vec3(-1.0, 1.0, 1.0)
+};
+
+// The same colours that `MODE_SHADOW_CASCADE` tints each cascade with.
+const vec3 COLOURS[3] = {
+    vec3(1.0, 0.25, 0.25),
+    vec3(0.25, 1.0, 0.25),
+    vec3(0.25, 0.25, 1.0)
+};
+
+// The cascade index is passed in as the instance index.
+void main() {
+    colour = vec4(COLOURS[gl_InstanceIndex], 1.0);
+
+    vec4 position = inverse(light_projection) * vec4(EDGES[gl_VertexIndex], 1.0);
+
+    gl_Position = camera.perspective_view * vec4(position.xyz / position.w, 1.0);
+}
@@ -0,0 +1,26 @@
+// The sand simulation and the tracks move the ground away from the baked
+// terrain. This expects `scene_bounds`, `clamp_sampler`, `sand_tracks` and
+// `sand_offset` to be declared.
+
+// How far the sand simulation has moved the sand from the baked terrain.
+float sample_sand_offset(vec2 pos) {
+    vec2 uv = (pos - scene_bounds.min) / (scene_bounds.max - scene_bounds.min);
+    return textureLod(sampler2D(sand_offset, clamp_sampler), uv, 0).r;
+}
+
+// How far the sand has moved from the baked terrain, from the simulation and
+// from the tracks pressed down into it.
+float sample_height_offset(vec2 pos) {
+    vec2 uv = (pos - scene_bounds.min) / (scene_bounds.max - scene_bounds.min);
+    return sample_sand_offset(pos) - textureLod(sampler2D(sand_tracks, clamp_sampler), uv, 0).r;
+}
+
+// Tilt a normal by the slope of the height offset.
+vec3 apply_height_offset(vec3 normal, vec2 pos) {
+    vec2 step = (scene_bounds.max - scene_bounds.min) / vec2(textureSize(sampler2D(sand_tracks, clamp_sampler), 0));
+
+    float d_x = sample_height_offset(pos + vec2(step.x, 0.0)) - sample_height_offset(pos - vec2(step.x, 0.0));
+    float d_z = sample_height_offset(pos + vec2(0.0, step.y)) - sample_height_offset(pos - vec2(0.0, step.y));
+
+    return normalize(normal - vec3(d_x / (step.x * 2.0), 0.0, d_z / (step.y * 2.0)));
+}
@@ -0,0 +1,6 @@
+// Whether the vehicles spawn their particles this step. This expects `time`
+// and `particles_info` to be declared.
+bool spawn_this_step() {
+    float cooldown = particles_info.spawn_cooldown;
+    return cooldown == 0.0 || time.time_since_start - particles_info.last_particle_spawn_time > cooldown;
+}
@@ -0,0 +1,17 @@
+// The simulation stores how far the sand has moved up or down from the baked
+// height map. This expects `scene_bounds`, `clamp_sampler`, `height_map` and
+// `sand_offset` to be declared.
+
+vec2 sand_simulation_texel_size() {
+    return (scene_bounds.max - scene_bounds.min) / vec2(textureSize(sampler2D(sand_offset, clamp_sampler), 0));
+}
+
+// The height of the sand at a uv over the scene bounds.
+float sand_height(vec2 uv) {
+    float height = textureLod(sampler2D(height_map, clamp_sampler), uv, 0).r;
+    return height + textureLod(sampler2D(sand_offset, clamp_sampler), uv, 0).r;
+}
+
+vec2 sand_simulation_uv(ivec2 texel) {
+    return (vec2(texel) + 0.5) / vec2(textureSize(sampler2D(sand_offset, clamp_sampler), 0));
+}
@@ -0,0 +1,6 @@
+// How long it takes for tracks to fill back in to ~37% of their depth.
+const float SAND_TRACKS_FILL_TIME = 30.0;
+
+float sand_tracks_fill_factor(float delta_time) {
+    return exp(-delta_time / SAND_TRACKS_FILL_TIME);
+}
@@ -1,3 +1,5 @@
+#include "../../cascaded-shadow-maps/shaders/moments.glsl"
+
 uint cascade_index(float view_pos_z, vec2 splits) {
 	// Compare the z against the split distances. We want to find out how many
     // splits the z is less than, as that's our cascade index.
@@ -47,13 +49,30 @@ float percentage_closer_filtering(vec2 light_local, uint cascade_index, float co
     return shadow_sum / ITERATONS;
 }
 
+float sample_moments(vec2 light_local, uint cascade_index, float depth, uint mode, float light_bleeding_reduction) {
+    vec4 moments = texture(
+        sampler2DArray(SHADOW_MOMENTS_TEXTURE_ARRAY, SHADOW_MOMENTS_SAMPLER),
+        vec3(light_local, float(cascade_index))
+    );
+
+    return moments_visibility(moments, depth, mode, light_bleeding_reduction);
+}
+
 // See https://github.com/gfx-rs/wgpu-rs/blob/cadc2df8a106ad122c10c2e07733ade8f1e5653c/examples/shadow/shader.wgsl#L67
-float calculate_shadow(float view_pos_z, mat4 matrices[3], vec2 splits, vec3 frag_pos) {
-	uint cascade_index = cascade_index(view_pos_z, splits);
-	vec4 transformed_coords = matrices[cascade_index] * vec4(frag_pos, 1.0);
+float calculate_shadow(float view_pos_z, CSM csm, vec3 frag_pos) {
+	uint cascade_index = cascade_index(view_pos_z, csm.split_depths);
+	vec4 transformed_coords = csm.matrices[cascade_index] * vec4(frag_pos, 1.0);
 
     vec3 proj_corrected = transformed_coords.xyz / transformed_coords.w;
     vec2 light_local = proj_corrected.xy;
+
+    if (csm.mode != SHADOW_MODE_DEPTH_COMPARE) {
+        // Filtered moments don't need a depth bias.
+        return sample_moments(
+            light_local, cascade_index, proj_corrected.z, csm.mode, csm.light_bleeding_reduction
+        );
+    }
+
     float bias = 0.005;
     float comparison = proj_corrected.z - bias;
 
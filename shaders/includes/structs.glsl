@@ -1,12 +1,19 @@
 
 struct Settings {
-    vec3 base_colour;
-    float detail_map_scale;
     vec3 ambient_lighting;
-    float roughness;
     float specular_factor;
     uint mode;
     float ship_movement_bounds;
+    float wind_direction;
+    float wind_strength;
+    uint flocking;
+    float boid_neighbour_radius;
+    float boid_separation_weight;
+    float boid_alignment_weight;
+    float boid_cohesion_weight;
+    float ship_clearance;
+    uint land_craft_avoidance;
+    float land_craft_radius;
 };
 
 const uint MODE_FULL = 0;
@@ -14,10 +21,37 @@ const uint MODE_NORMALS = 1;
 const uint MODE_NOISE = 2;
 const uint MODE_HUE_NOISE = 3;
 const uint MODE_SHADOW_CASCADE = 4;
+const uint MODE_TRIPLANAR_WEIGHTS = 5;
+
+const uint NUM_TERRAIN_LAYERS = 4;
+
+struct TerrainLayer {
+    vec3 albedo;
+    float roughness;
+    float detail_scale;
+    float normal_strength;
+    vec2 slope_range;
+    vec2 height_range;
+};
+
+struct TerrainMaterial {
+    TerrainLayer layers[NUM_TERRAIN_LAYERS];
+    uint blend_mode;
+    float blend_softness;
+    uint detail_projection;
+};
+
+const uint TERRAIN_BLEND_MODE_RULES = 0;
+const uint TERRAIN_BLEND_MODE_SPLAT_MAP = 1;
+
+const uint DETAIL_PROJECTION_UV = 0;
+const uint DETAIL_PROJECTION_TRIPLANAR = 1;
 
 struct CSM {
     mat4 matrices[3];
     vec2 split_depths;
+    uint mode;
+    float light_bleeding_reduction;
 };
 
 struct Camera {
@@ -30,8 +64,9 @@ struct Camera {
 struct Ship {
     vec3 position;
     float facing;
-    mat3 y_rotation_matrix;
+    mat3 rotation_matrix;
     float rotation_speed;
+    float roll;
 };
 
 struct Sun {
@@ -44,28 +79,76 @@ const uint TONEMAPPER_MODE_NO_CROSSTALK = 1;
 const uint TONEMAPPER_MODE_OFF = 2;
 const uint TONEMAPPER_MODE_WASM_GAMMA_CORRECT = 3;
 
+// This needs to match `MAX_SPAWN_OFFSETS` in `primitives`.
+const uint MAX_SPAWN_OFFSETS = 4;
+
 struct ParticlesBufferInfo {
     vec3 colour;
     uint offset;
     float particle_duration;
     float half_size_linear;
     float last_particle_spawn_time;
+    // Particles are spawned every step if this is 0.
+    float spawn_cooldown;
+    // Where the particles are spawned from, relative to each vehicle.
+    vec4 spawn_offsets[MAX_SPAWN_OFFSETS];
+    uint num_spawn_offsets;
+    // The speed that particles are spawned with along the vehicle's up axis.
+    float up_speed;
+    // The speed that particles are spawned with in a random direction.
+    float random_speed;
 };
 
 struct Particle {
     vec3 position;
     float time_alive_percentage;
     vec3 velocity;
-    vec3 view_space_position;
 };
 
 struct Time {
     float time_since_start;
     float delta_time;
+    uint frame;
+};
+
+struct SceneBounds {
+    vec2 min;
+    vec2 max;
+    float min_height;
+    float max_height;
+};
+
+// The part that every type of vehicle starts with, whichever way it moves.
+struct Vehicle {
+    vec3 position;
+    float facing;
+    mat3 rotation_matrix;
+    uvec4 movement;
+};
+
+struct VehicleTypeInfo {
+    // UVs with a v above this are scrolled along u, such as for treads.
+    float uv_scroll_threshold;
+    float uv_scroll_speed;
+    // How far flying vehicles bob around their clearance.
+    float bob_amplitude;
+};
+
+struct VehicleGrid {
+    // Whether the grid wraps around, as flying vehicles do.
+    uint wraps;
 };
 
 struct LandCraft {
     vec3 position;
     float facing;
     mat3 rotation_matrix;
+    uint route;
+    float route_progress;
+    float speed;
+};
+
+struct Route {
+    uint start;
+    uint len;
 };
@@ -0,0 +1,79 @@
+// Places the vertices of the chunked terrain. This expects `scene_bounds`,
+// `clamp_sampler`, `height_map`, `height_tiles`, `sand_tracks` and `sand_offset`
+// to be declared.
+
+// The number of quads along each side of a chunk at the highest level of detail.
+const uint CHUNK_RESOLUTION = 64;
+
+// Move a vertex along an edge onto the grid of a coarser neighbour, so that both
+// chunks agree on where the edge is.
+float snap_to_neighbour(float coord, uint lod, uint neighbour_lod) {
+    if (neighbour_lod <= lod) {
+        return coord;
+    }
+
+    float ratio = float(1 << (neighbour_lod - lod));
+    return floor(coord / ratio) * ratio;
+}
+
+// The position of a vertex on the chunk's grid, in quads from its corner.
+vec2 chunk_vertex_grid(vec2 local, uint lod, uvec4 neighbour_lods) {
+    float steps = float(CHUNK_RESOLUTION >> lod);
+    vec2 grid = round(local * steps);
+
+    if (grid.x == 0.0) {
+        grid.y = snap_to_neighbour(grid.y, lod, neighbour_lods.x);
+    } else if (grid.x == steps) {
+        grid.y = snap_to_neighbour(grid.y, lod, neighbour_lods.y);
+    }
+
+    if (grid.y == 0.0) {
+        grid.x = snap_to_neighbour(grid.x, lod, neighbour_lods.z);
+    } else if (grid.y == steps) {
+        grid.x = snap_to_neighbour(grid.x, lod, neighbour_lods.w);
+    }
+
+    return grid;
+}
+
+vec2 chunk_grid_xz(vec2 grid, vec2 origin, vec2 size, uint lod) {
+    return origin + grid / float(CHUNK_RESOLUTION >> lod) * size;
+}
+
+vec2 chunk_scene_uv(vec2 xz) {
+    return (xz - scene_bounds.min) / (scene_bounds.max - scene_bounds.min);
+}
+
+// Whether the height map covers a vertex. Vertices along the edges of the scene
+// bounds count as covered, so that the chunks on either side agree on them.
+bool chunk_in_scene(vec2 uv) {
+    const float epsilon = 0.0001;
+    return all(greaterThanEqual(uv, vec2(-epsilon))) && all(lessThanEqual(uv, vec2(1.0 + epsilon)));
+}
+
+// The height of the baked terrain, with the simulated sand and tracks on top.
+float chunk_height(vec2 uv) {
+    float height = textureLod(sampler2D(height_map, clamp_sampler), uv, 0).r;
+    height += textureLod(sampler2D(sand_offset, clamp_sampler), uv, 0).r;
+    return height - textureLod(sampler2D(sand_tracks, clamp_sampler), uv, 0).r;
+}
+
+// Where a vertex's height is in the tile atlas. Chunks are never drawn finer
+// than their tile, so every vertex lands on a streamed height.
+ivec2 chunk_tile_texel(vec2 grid, uvec2 tile, uint lod, uint tile_lod) {
+    // Skip over the border of the tile.
+    return ivec2(tile) + 1 + ivec2(grid) * (1 << (lod - tile_lod));
+}
+
+float chunk_tile_height(ivec2 texel) {
+    return texelFetch(sampler2D(height_tiles, clamp_sampler), texel, 0).r;
+}
+
+// The normal from the slope between neighbouring heights in the tile, which are
+// `spacing` apart.
+vec3 chunk_tile_normal(ivec2 texel, vec2 spacing) {
+    float d_x = chunk_tile_height(texel + ivec2(1, 0)) - chunk_tile_height(texel - ivec2(1, 0));
+    float d_z = chunk_tile_height(texel + ivec2(0, 1)) - chunk_tile_height(texel - ivec2(0, 1));
+
+    return normalize(vec3(-d_x / (spacing.x * 2.0), 1.0, -d_z / (spacing.y * 2.0)));
+}
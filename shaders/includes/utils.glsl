@@ -37,10 +37,39 @@ vec2 repeat_over_bounds(vec2 position, float bounds) {
     return position - offset;
 }
 
-vec3 randomish_unit_vector(vec3 seed) {
+// Wrap a position that has gone past one side of an area around to the other side.
+vec2 repeat_over_area(vec2 position, vec2 area_min, vec2 area_max) {
+    return area_min + mod(position - area_min, area_max - area_min);
+}
+
+// https://nullprogram.com/blog/2018/07/31/
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352dU;
+    x ^= x >> 15;
+    x *= 0x846ca68bU;
+    x ^= x >> 16;
+    return x;
+}
+
+float hash_to_unit_float(uint x) {
+    return float(x >> 8) / 16777216.0;
+}
+
+// Hashed from the bits of the seed and the frame, so that it changes every
+// frame but is the same between runs that are started with the same seed.
+vec3 randomish_unit_vector(vec3 seed, uint frame) {
+    uint x = hash(frame);
+    x = hash(x ^ floatBitsToUint(seed.x));
+    x = hash(x ^ floatBitsToUint(seed.y));
+    x = hash(x ^ floatBitsToUint(seed.z));
+
+    uint y = hash(x);
+    uint z = hash(y);
+
     return normalize(vec3(
-        random(seed.xy) - 0.5,
-        random(seed.yz) - 0.5,
-        random(seed.zx) - 0.5
+        hash_to_unit_float(x) - 0.5,
+        hash_to_unit_float(y) - 0.5,
+        hash_to_unit_float(z) - 0.5
     ));
 }
@@ -0,0 +1,108 @@
+// The vehicles of one type are sorted into a grid of cells, with each cell at
+// least as wide as the distance that the vehicles look for each other within,
+// so that each vehicle only needs to look at the vehicles in the cells around
+// it.
+//
+// Grids that wrap cover the area that flying vehicles wrap around in, with
+// cells as wide as the flocking neighbour radius. Grids that don't wrap cover
+// the scene, with cells as wide as the distance that driving vehicles start
+// avoiding each other from.
+//
+// This expects `settings` and `scene_bounds` to be declared, and declares the
+// grid itself in set 2.
+
+// This needs to match `MAX_GRID_SIZE` in `resource_creation.rs`.
+const uint MAX_GRID_SIZE = 64;
+
+// Driving vehicles start steering around each other when they're this many radii apart.
+const float CRAFT_AVOIDANCE_RADII = 4.0;
+
+struct GridCell {
+    uint count;
+    uint start;
+};
+
+layout(set = 2, binding = 0) uniform VehicleGridUniform {
+    VehicleGrid grid;
+};
+
+layout(set = 2, binding = 1) buffer GridCells {
+    GridCell cells[MAX_GRID_SIZE * MAX_GRID_SIZE];
+};
+
+layout(set = 2, binding = 2) buffer VehicleCells {
+    // The index of the cell that each vehicle is in, and its slot within that cell.
+    uvec2 vehicle_cells[];
+};
+
+layout(set = 2, binding = 3) buffer SortedVehicles {
+    // The position on the xz plane, facing and index of each vehicle, sorted
+    // by cell and then by index.
+    vec4 sorted_vehicles[];
+};
+
+float craft_avoidance_distance() {
+    return settings.land_craft_radius * CRAFT_AVOIDANCE_RADII;
+}
+
+float grid_area_size() {
+    if (grid.wraps != 0) {
+        return settings.ship_movement_bounds * 2.0;
+    }
+
+    vec2 size = scene_bounds.max - scene_bounds.min;
+    return max(size.x, size.y);
+}
+
+vec2 grid_area_min() {
+    if (grid.wraps != 0) {
+        vec2 scene_center = (scene_bounds.min + scene_bounds.max) * 0.5;
+        return scene_center - settings.ship_movement_bounds;
+    }
+
+    return scene_bounds.min;
+}
+
+// Kept within a third of the grid, so that the cells are never narrower than
+// it. This matches `Settings::max_boid_neighbour_radius`.
+float boid_neighbour_radius() {
+    return min(settings.boid_neighbour_radius, grid_area_size() / 3.0);
+}
+
+int grid_size() {
+    float cell_size = grid.wraps != 0 ? boid_neighbour_radius() : craft_avoidance_distance();
+    // With fewer than 3 cells, the neighbouring cells would wrap around to the same ones.
+    float min_size = grid.wraps != 0 ? 3.0 : 1.0;
+    return int(clamp(floor(grid_area_size() / cell_size), min_size, float(MAX_GRID_SIZE)));
+}
+
+ivec2 grid_cell_coord(vec2 position) {
+    int size = grid_size();
+    vec2 uv = (position - grid_area_min()) / grid_area_size();
+    return clamp(ivec2(floor(uv * float(size))), ivec2(0), ivec2(size - 1));
+}
+
+uint grid_cell_index(ivec2 coord) {
+    return uint(coord.y * grid_size() + coord.x);
+}
+
+// The index of a cell next to another, wrapping around if the grid does.
+// Returns false if the cell is off the edge of a grid that doesn't wrap.
+bool grid_neighbour_cell(ivec2 coord, out uint index) {
+    int size = grid_size();
+
+    if (grid.wraps != 0) {
+        coord = (coord + size) % size;
+    } else if (any(lessThan(coord, ivec2(0))) || any(greaterThanEqual(coord, ivec2(size)))) {
+        return false;
+    }
+
+    index = grid_cell_index(coord);
+    return true;
+}
+
+// Where a vehicle is in `sorted_vehicles`.
+uint grid_sorted_index(uint index) {
+    uvec2 vehicle_cell = vehicle_cells[index];
+    return cells[vehicle_cell.x].start + vehicle_cell.y;
+}
@@ -46,7 +46,9 @@ void main() {
 
     out_coord = coord;
 
-    vec3 view_space = particle.view_space_position + half_size * vec3(coord, 0.0);
+    // This is done here rather than when moving the particles, as the camera can
+    // move while the simulation is paused.
+    vec3 view_space = (camera.view * vec4(particle.position, 1.0)).xyz + half_size * vec3(coord, 0.0);
 
     gl_Position = camera.perspective * vec4(view_space, 1.0);
 }
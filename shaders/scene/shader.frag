@@ -23,8 +23,23 @@ layout(set = 0, binding = 3) uniform SettingsUniform {
     Settings settings;
 };
 
+layout(set = 0, binding = 5) uniform sampler clamp_sampler;
+
+layout(set = 0, binding = 6) uniform SceneBoundsUniform {
+    SceneBounds scene_bounds;
+};
+
+layout(set = 0, binding = 7) uniform texture2D sand_tracks;
+
+layout(set = 0, binding = 8) uniform texture2D sand_offset;
+
+layout(set = 0, binding = 9) uniform TerrainMaterialUniform {
+    TerrainMaterial terrain_material;
+};
+
 layout(set = 1, binding = 0) uniform texture2D u_normals_texture;
-layout(set = 1, binding = 1) uniform texture2D u_details_texture;
+layout(set = 1, binding = 1) uniform texture2DArray u_layer_details_texture;
+layout(set = 1, binding = 2) uniform texture2D u_splat_texture;
 
 layout(set = 2, binding = 0) uniform texture2DArray shadow_texture_array;
 
@@ -34,35 +49,157 @@ layout(set = 2, binding = 2) uniform CascadedShadowMapUniform {
     CSM csm;
 };
 
+layout(set = 2, binding = 3) uniform texture2DArray shadow_moments_texture_array;
+
+layout(set = 2, binding = 4) uniform sampler shadow_moments_sampler;
+
 #define SHADOW_SAMPLER shadow_sampler
 #define SHADOW_TEXTURE_ARRAY shadow_texture_array
+#define SHADOW_MOMENTS_SAMPLER shadow_moments_sampler
+#define SHADOW_MOMENTS_TEXTURE_ARRAY shadow_moments_texture_array
 #include "../includes/shadows.glsl"
+#include "../includes/height_offset.glsl"
 
-// todo: use a better blending function than this.
+// How sharply the triplanar projection transitions between axes.
+const float TRIPLANAR_SHARPNESS = 4.0;
+
+vec3 unpack_normal(vec3 normal) {
+    return normal * 2.0 - 1.0;
+}
+
+// Reoriented normal mapping: rotate the detail normal so that it sits on the
+// surface of the base normal, instead of just adding their slopes together.
+// Both normals are in tangent space with z pointing out of the surface.
 // https://blog.selfshadow.com/publications/blending-in-detail/
-vec3 blend_normals(vec3 a, vec3 b) {
-    float xs = a.x + b.x;
-    float ys = a.y + b.y;
-    return normalize(vec3(xs, ys, a.z)) * 2.0 - 1.0;
+vec3 reorient_normal(vec3 base, vec3 detail) {
+    vec3 t = base + vec3(0.0, 0.0, 1.0);
+    vec3 u = detail * vec3(-1.0, -1.0, 1.0);
+    return normalize(t * dot(t, u) / t.z - u);
 }
 
 vec3 normal_to_view_space(vec3 normal) {
     return normal * 0.5 + 0.5;
 }
 
+// 1 inside the range, fading to 0 over `softness` past either end.
+float in_range(float value, vec2 range, float softness) {
+    return smoothstep(range.x - softness, range.x, value) *
+        (1.0 - smoothstep(range.y, range.y + softness, value));
+}
+
+// How much each terrain layer contributes to the surface, summing to 1.
+vec4 terrain_layer_weights(vec3 normal) {
+    vec4 weights;
+
+    if (terrain_material.blend_mode == TERRAIN_BLEND_MODE_SPLAT_MAP) {
+        weights = textureLod(sampler2D(u_splat_texture, clamp_sampler), in_uv, 0);
+    } else {
+        float slope = acos(clamp(normal.y, -1.0, 1.0));
+        float height = (in_pos.y - scene_bounds.min_height) / max(scene_bounds.max_height - scene_bounds.min_height, 1e-5);
+        float softness = terrain_material.blend_softness;
+
+        for (uint i = 0; i < NUM_TERRAIN_LAYERS; i++) {
+            TerrainLayer layer = terrain_material.layers[i];
+            weights[i] = in_range(slope, layer.slope_range, softness) *
+                in_range(height, layer.height_range, softness);
+        }
+    }
+
+    float total = weights.x + weights.y + weights.z + weights.w;
+
+    // Fall back to the first layer where none of them apply.
+    if (total < 0.0001) {
+        return vec4(1.0, 0.0, 0.0, 0.0);
+    }
+
+    return weights / total;
+}
+
+vec3 sample_detail_normal(vec2 uv, uint layer_index, float strength) {
+    vec3 detail_uv = vec3(uv, float(layer_index));
+    vec3 normal = unpack_normal(textureLod(sampler2DArray(u_layer_details_texture, u_sampler), detail_uv, 0).xyz);
+    // Flatten the detail normal towards +z in tangent space.
+    return normalize(mix(vec3(0.0, 0.0, 1.0), normal, strength));
+}
+
+// How much each of the x, y and z axis projections contribute to a surface
+// facing along `normal`.
+vec3 triplanar_weights(vec3 normal) {
+    vec3 weights = pow(abs(normal), vec3(TRIPLANAR_SHARPNESS));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+// Project the detail map along each world axis and reorient each projection
+// onto the surface, returning a world space normal. This avoids the stretching
+// that uv mapping has on steep slopes.
+// https://bgolus.medium.com/normal-mapping-for-a-triplanar-shader-10bf39dca05a
+vec3 triplanar_detail_normal(vec3 normal, vec3 weights, uint layer_index, TerrainLayer layer) {
+    // Match the density of the uv mapping, which covers the scene once.
+    vec2 scene_size = scene_bounds.max - scene_bounds.min;
+    vec3 pos = in_pos / max(scene_size.x, scene_size.y) * layer.detail_scale;
+
+    vec3 normal_x = sample_detail_normal(pos.zy, layer_index, layer.normal_strength);
+    vec3 normal_y = sample_detail_normal(pos.xz, layer_index, layer.normal_strength);
+    vec3 normal_z = sample_detail_normal(pos.xy, layer_index, layer.normal_strength);
+
+    vec3 abs_normal = abs(normal);
+
+    normal_x = reorient_normal(vec3(normal.zy, abs_normal.x), normal_x);
+    normal_y = reorient_normal(vec3(normal.xz, abs_normal.y), normal_y);
+    normal_z = reorient_normal(vec3(normal.xy, abs_normal.z), normal_z);
+
+    // Flip the projections on the negative sides of each axis.
+    vec3 axis_sign = mix(vec3(-1.0), vec3(1.0), step(0.0, normal));
+    normal_x.z *= axis_sign.x;
+    normal_y.z *= axis_sign.y;
+    normal_z.z *= axis_sign.z;
+
+    return normalize(
+        normal_x.zyx * weights.x +
+        normal_y.xzy * weights.y +
+        normal_z.xyz * weights.z
+    );
+}
+
 void main() {
     vec3 normal = normalize(in_normal);
     vec3 tangent = normalize(in_tangent.xyz);
     vec3 bitangent = cross(in_normal, in_tangent.xyz) * in_tangent.w;
     mat3 TBN = mat3(tangent, bitangent, normal);
 
-    vec4 map_normal = textureLod(sampler2D(u_normals_texture, u_sampler), in_uv, 0);
-    vec2 detail_uv = in_uv * settings.detail_map_scale;
-    vec4 detail_normal = textureLod(sampler2D(u_details_texture, u_sampler), detail_uv, 0);
+    vec4 layer_weights = terrain_layer_weights(normal);
+
+    vec3 map_normal = unpack_normal(textureLod(sampler2D(u_normals_texture, u_sampler), in_uv, 0).xyz);
+    vec3 base_normal = normalize(TBN * map_normal);
+
+    vec3 weights = triplanar_weights(base_normal);
+    bool triplanar = terrain_material.detail_projection == DETAIL_PROJECTION_TRIPLANAR;
+
+    vec3 detail_normal = vec3(0.0);
+    vec3 base_colour = vec3(0.0);
+    float roughness = 0.0;
 
-    vec3 local_normal = blend_normals(map_normal.xyz, detail_normal.xyz);
+    for (uint i = 0; i < NUM_TERRAIN_LAYERS; i++) {
+        TerrainLayer layer = terrain_material.layers[i];
 
-    normal = normalize(TBN * local_normal);
+        vec3 layer_normal;
+
+        if (triplanar) {
+            layer_normal = triplanar_detail_normal(base_normal, weights, i, layer);
+        } else {
+            vec3 local_normal = reorient_normal(
+                map_normal,
+                sample_detail_normal(in_uv * layer.detail_scale, i, layer.normal_strength)
+            );
+            layer_normal = TBN * local_normal;
+        }
+
+        detail_normal += layer_normal * layer_weights[i];
+        base_colour += layer.albedo * layer_weights[i];
+        roughness += layer.roughness * layer_weights[i];
+    }
+
+    normal = apply_height_offset(normalize(detail_normal), in_pos.xz);
 
     vec3 camera_dir = normalize(in_camera_dir);
     vec3 halfway_dir = normalize(sun.facing + camera_dir);
@@ -70,7 +207,7 @@ void main() {
     vec3 f0 = vec3(0.04);
     vec3 f90 = compute_f90(f0);
 
-    float alpha_roughness = settings.roughness * settings.roughness;
+    float alpha_roughness = roughness * roughness;
 
     float NdotL = clamped_dot(normal, sun.facing);
     float VdotH = clamped_dot(camera_dir, halfway_dir);
@@ -80,7 +217,7 @@ void main() {
     vec3 lighting_factor = sun.light_output * NdotL;
 
     vec3 diffuse =  lighting_factor *
-        BRDF_lambertian(f0, f90, settings.base_colour, VdotH);
+        BRDF_lambertian(f0, f90, base_colour, VdotH);
     vec3 specular = lighting_factor *
         BRDF_specularGGX(f0, f90, alpha_roughness, VdotH, NdotL, NdotV, NdotH);
 
@@ -89,7 +226,7 @@ void main() {
 
     specular *= settings.specular_factor * hue_noise;
 
-    float shadow = calculate_shadow(in_view_pos.z, csm.matrices, csm.split_depths, in_pos);
+    float shadow = calculate_shadow(in_view_pos.z, csm, in_pos);
 
     float diffuse_shadow_amount = 0.1;
     float diffuse_shadowing = shadow * (1.0 - diffuse_shadow_amount) + diffuse_shadow_amount;
@@ -114,6 +251,9 @@ void main() {
             uint cascade_index = cascade_index(in_view_pos.z, csm.split_depths);
             colour *= debug_colour_for_cascade(cascade_index);
             break;
+        case MODE_TRIPLANAR_WEIGHTS:
+            colour = weights;
+            break;
     }
 
     out_colour = vec4(colour, 1.0);
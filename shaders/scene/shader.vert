@@ -18,13 +18,27 @@ layout(set = 0, binding = 0) uniform CameraUniform {
     Camera camera;
 };
 
+layout(set = 0, binding = 5) uniform sampler clamp_sampler;
+
+layout(set = 0, binding = 6) uniform SceneBoundsUniform {
+    SceneBounds scene_bounds;
+};
+
+layout(set = 0, binding = 7) uniform texture2D sand_tracks;
+
+layout(set = 0, binding = 8) uniform texture2D sand_offset;
+
+#include "../includes/height_offset.glsl"
+
 void main() {
+    vec3 pos = position + vec3(0.0, sample_height_offset(position.xz), 0.0);
+
     out_normal = normal;
     out_uv = uv;
     out_tangent = tangent;
-    out_camera_dir = camera.position - position;
-    out_pos = position;
-    out_view_pos = (camera.view * vec4(position, 1.0)).xyz;
+    out_camera_dir = camera.position - pos;
+    out_pos = pos;
+    out_view_pos = (camera.view * vec4(pos, 1.0)).xyz;
 
-    gl_Position = camera.perspective_view * vec4(position, 1.0);
+    gl_Position = camera.perspective_view * vec4(pos, 1.0);
 }
@@ -1,5 +1,7 @@
 #version 450
 
+#include "../includes/structs.glsl"
+
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 uv;
@@ -9,6 +11,22 @@ layout(set = 0, binding = 0) uniform SunProjectionView {
     mat4 projection_view;
 };
 
+layout(set = 1, binding = 5) uniform sampler clamp_sampler;
+
+layout(set = 1, binding = 6) uniform SceneBoundsUniform {
+    SceneBounds scene_bounds;
+};
+
+layout(set = 1, binding = 7) uniform texture2D sand_tracks;
+
+layout(set = 1, binding = 8) uniform texture2D sand_offset;
+
+#include "../includes/height_offset.glsl"
+
 void main() {
-    gl_Position = projection_view * vec4(position, 1.0);
+    // Cast shadows from where the sand simulation has moved the sand to. The
+    // tracks are left out, as these shadows are cached between steps.
+    vec3 pos = position + vec3(0.0, sample_sand_offset(position.xz), 0.0);
+
+    gl_Position = projection_view * vec4(pos, 1.0);
 }
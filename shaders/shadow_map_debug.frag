@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 colour;
+
+layout(set = 0, binding = 0) uniform texture2D shadow_map;
+layout(set = 0, binding = 1) uniform sampler shadow_map_sampler;
+
+layout(set = 0, binding = 2) uniform ShadowDebugSettings {
+    float depth_min;
+    float depth_max;
+};
+
+// Remap a shadow map's depths into a visible greyscale range.
+void main() {
+    ivec2 size = textureSize(sampler2D(shadow_map, shadow_map_sampler), 0);
+    ivec2 coord = min(ivec2(uv * vec2(size)), size - 1);
+
+    float depth = texelFetch(sampler2D(shadow_map, shadow_map_sampler), coord, 0).r;
+    float remapped = clamp((depth - depth_min) / max(depth_max - depth_min, 0.0001), 0.0, 1.0);
+
+    colour = vec4(vec3(remapped), 1.0);
+}
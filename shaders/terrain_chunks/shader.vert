@@ -0,0 +1,81 @@
+#version 450
+
+#include "../includes/structs.glsl"
+
+layout(location = 0) in vec2 local;
+layout(location = 1) in vec2 chunk_origin;
+layout(location = 2) in vec2 chunk_size;
+layout(location = 3) in uint chunk_lod;
+layout(location = 4) in uvec4 chunk_neighbour_lods;
+layout(location = 5) in uvec2 chunk_tile;
+layout(location = 6) in uint chunk_tile_lod;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) out vec2 out_uv;
+layout(location = 2) out vec4 out_tangent;
+layout(location = 3) out vec3 out_camera_dir;
+layout(location = 4) out vec3 out_pos;
+layout(location = 5) out vec3 out_view_pos;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+    Camera camera;
+};
+
+layout(set = 0, binding = 5) uniform sampler clamp_sampler;
+
+layout(set = 0, binding = 6) uniform SceneBoundsUniform {
+    SceneBounds scene_bounds;
+};
+
+layout(set = 0, binding = 7) uniform texture2D sand_tracks;
+
+layout(set = 0, binding = 8) uniform texture2D sand_offset;
+
+layout(set = 3, binding = 0) uniform texture2D height_map;
+
+layout(set = 3, binding = 1) uniform texture2D height_map_normals;
+
+layout(set = 3, binding = 2) uniform texture2D height_map_uvs;
+
+layout(set = 3, binding = 3) uniform texture2D height_map_tangents;
+
+layout(set = 3, binding = 4) uniform texture2D height_tiles;
+
+#include "../includes/terrain_chunks.glsl"
+
+void main() {
+    vec2 grid = chunk_vertex_grid(local, chunk_lod, chunk_neighbour_lods);
+    vec2 xz = chunk_grid_xz(grid, chunk_origin, chunk_size, chunk_lod);
+    vec2 uv = chunk_scene_uv(xz);
+
+    vec3 pos;
+    vec3 normal;
+    vec4 tangent;
+
+    if (chunk_in_scene(uv)) {
+        pos = vec3(xz.x, chunk_height(uv), xz.y);
+        normal = normalize(textureLod(sampler2D(height_map_normals, clamp_sampler), uv, 0).xyz);
+        tangent = textureLod(sampler2D(height_map_tangents, clamp_sampler), uv, 0);
+        // The scene's textures are mapped with the uvs of the terrain mesh.
+        out_uv = textureLod(sampler2D(height_map_uvs, clamp_sampler), uv, 0).xy;
+    } else {
+        ivec2 texel = chunk_tile_texel(grid, chunk_tile, chunk_lod, chunk_tile_lod);
+        vec2 spacing = chunk_size / float(CHUNK_RESOLUTION >> chunk_tile_lod);
+
+        pos = vec3(xz.x, chunk_tile_height(texel), xz.y);
+        normal = chunk_tile_normal(texel, spacing);
+        tangent = vec4(1.0, 0.0, 0.0, 1.0);
+        // Past the edges of the terrain mesh, carry on mapping the textures
+        // across the scene bounds.
+        out_uv = uv;
+    }
+
+    out_normal = normal;
+    // Keep the tangent at right angles to the normal after filtering.
+    out_tangent = vec4(normalize(tangent.xyz - normal * dot(normal, tangent.xyz)), sign(tangent.w));
+    out_camera_dir = camera.position - pos;
+    out_pos = pos;
+    out_view_pos = (camera.view * vec4(pos, 1.0)).xyz;
+
+    gl_Position = camera.perspective_view * vec4(pos, 1.0);
+}
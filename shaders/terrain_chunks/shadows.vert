@@ -0,0 +1,43 @@
+#version 450
+
+#include "../includes/structs.glsl"
+
+layout(location = 0) in vec2 local;
+layout(location = 1) in vec2 chunk_origin;
+layout(location = 2) in vec2 chunk_size;
+layout(location = 3) in uint chunk_lod;
+layout(location = 4) in uvec4 chunk_neighbour_lods;
+layout(location = 5) in uvec2 chunk_tile;
+layout(location = 6) in uint chunk_tile_lod;
+
+layout(set = 0, binding = 0) uniform SunProjectionView {
+    mat4 projection_view;
+};
+
+layout(set = 1, binding = 5) uniform sampler clamp_sampler;
+
+layout(set = 1, binding = 6) uniform SceneBoundsUniform {
+    SceneBounds scene_bounds;
+};
+
+layout(set = 1, binding = 7) uniform texture2D sand_tracks;
+
+layout(set = 1, binding = 8) uniform texture2D sand_offset;
+
+layout(set = 2, binding = 0) uniform texture2D height_map;
+
+layout(set = 2, binding = 4) uniform texture2D height_tiles;
+
+#include "../includes/terrain_chunks.glsl"
+
+void main() {
+    vec2 grid = chunk_vertex_grid(local, chunk_lod, chunk_neighbour_lods);
+    vec2 xz = chunk_grid_xz(grid, chunk_origin, chunk_size, chunk_lod);
+    vec2 uv = chunk_scene_uv(xz);
+
+    float height = chunk_in_scene(uv)
+        ? chunk_height(uv)
+        : chunk_tile_height(chunk_tile_texel(grid, chunk_tile, chunk_lod, chunk_tile_lod));
+
+    gl_Position = projection_view * vec4(xz.x, height, xz.y, 1.0);
+}
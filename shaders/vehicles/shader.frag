@@ -24,6 +24,10 @@ layout(set = 0, binding = 4) uniform TimeBuffer {
     Time time;
 };
 
+layout(set = 1, binding = 1) uniform VehicleTypeInfoUniform {
+    VehicleTypeInfo type_info;
+};
+
 layout(set = 2, binding = 0) uniform texture2D u_texture;
 
 layout(set = 3, binding = 0) uniform texture2DArray shadow_texture_array;
@@ -34,15 +38,18 @@ layout(set = 3, binding = 2) uniform CascadedShadowMapUniform {
     CSM csm;
 };
 
+layout(set = 3, binding = 3) uniform texture2DArray shadow_moments_texture_array;
+
+layout(set = 3, binding = 4) uniform sampler shadow_moments_sampler;
+
 #define SHADOW_SAMPLER shadow_sampler
 #define SHADOW_TEXTURE_ARRAY shadow_texture_array
+#define SHADOW_MOMENTS_SAMPLER shadow_moments_sampler
+#define SHADOW_MOMENTS_TEXTURE_ARRAY shadow_moments_texture_array
 #include "../includes/shadows.glsl"
 
 layout(location = 0) out vec4 out_colour;
 
-// All UVs below this are part of the treads and should be rotated.
-const float UV_ROTATION_THRESHOLD = 1.0 - 0.186;
-
 void main() {
     vec3 normal = normalize(in_normal);
 
@@ -50,7 +57,10 @@ void main() {
     vec3 halfway_dir = normalize(sun.facing + camera_dir);
 
     vec2 uv = in_uv;
-    uv.x = fract(uv.x - float(uv.y > UV_ROTATION_THRESHOLD) * time.time_since_start);
+
+    if (uv.y > type_info.uv_scroll_threshold) {
+        uv.x = fract(uv.x - time.time_since_start * type_info.uv_scroll_speed);
+    }
 
     vec3 texture_colour = texture(sampler2D(u_texture, u_sampler), uv).rgb;
 
@@ -67,7 +77,7 @@ void main() {
     vec3 diffuse = lighting_factor *
         BRDF_lambertian(f0, f90, texture_colour, VdotH);
 
-    float shadow = calculate_shadow(in_view_pos.z, csm.matrices, csm.split_depths, in_pos);
+    float shadow = calculate_shadow(in_view_pos.z, csm, in_pos);
 
     float diffuse_shadow_amount = 0.1;
     float diffuse_shadowing = shadow * (1.0 - diffuse_shadow_amount) + diffuse_shadow_amount;
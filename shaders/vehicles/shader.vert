@@ -17,16 +17,16 @@ layout(set = 0, binding = 0) uniform CameraUniform {
     Camera camera;
 };
 
-layout(set = 1, binding = 0) readonly buffer LandCraftBuffer {
-    LandCraft crafts[];
+layout(set = 1, binding = 0) readonly buffer Vehicles {
+    Vehicle vehicles[];
 };
 
 void main() {
-    LandCraft craft = crafts[gl_InstanceIndex];
+    Vehicle vehicle = vehicles[gl_InstanceIndex];
 
-    mat3 rotation = craft.rotation_matrix;
+    mat3 rotation = vehicle.rotation_matrix;
 
-    vec3 transformed_pos = craft.position + rotation * position;
+    vec3 transformed_pos = vehicle.position + rotation * position;
 
     out_normal = rotation * normal;
     out_uv = uv;
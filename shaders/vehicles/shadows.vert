@@ -11,14 +11,16 @@ layout(set = 0, binding = 0) uniform SunProjectionView {
     mat4 projection_view;
 };
 
-layout(set = 1, binding = 0) readonly buffer LandCraftBuffer {
-    LandCraft crafts[];
+layout(set = 1, binding = 0) readonly buffer Vehicles {
+    Vehicle vehicles[];
 };
 
 void main() {
-    LandCraft craft = crafts[gl_InstanceIndex];
+    Vehicle vehicle = vehicles[gl_InstanceIndex];
 
-    vec3 transformed_pos = craft.position + craft.rotation_matrix * position;
+    mat3 rotation = vehicle.rotation_matrix;
+
+    vec3 transformed_pos = vehicle.position + rotation * position;
 
     gl_Position = projection_view * vec4(transformed_pos, 1.0);
 }
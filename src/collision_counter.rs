@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>;
+
+/// Reads the number of overlapping land craft back from the gpu, to be shown
+/// in the ui. Only one read is in flight at a time, so the count lags a few
+/// frames behind.
+pub struct CollisionCounter {
+    /// Written to by the land craft movement shader.
+    pub buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    mapping: Option<MapFuture>,
+    copy_submitted: bool,
+    num_collisions: u32,
+}
+
+impl CollisionCounter {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("land craft collisions buffer"),
+                size: 4,
+                usage: wgpu::BufferUsage::STORAGE
+                    | wgpu::BufferUsage::COPY_SRC
+                    | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("land craft collisions readback buffer"),
+                size: 4,
+                usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            mapping: None,
+            copy_submitted: false,
+            num_collisions: 0,
+        }
+    }
+
+    /// The number of overlapping pairs of land craft in the last step that was
+    /// read back.
+    pub fn num_collisions(&self) -> u32 {
+        self.num_collisions
+    }
+
+    /// Start counting again for the next step. Every type of land craft adds to
+    /// the same count.
+    pub fn reset(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&0_u32));
+    }
+
+    /// Copy the count from the latest step to be read back, unless a read is
+    /// still in flight.
+    pub fn copy(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.mapping.is_none() && !self.copy_submitted {
+            encoder.copy_buffer_to_buffer(&self.buffer, 0, &self.readback_buffer, 0, 4);
+            self.copy_submitted = true;
+        }
+    }
+
+    /// Start reading back a copy once it's been submitted, and pick up the
+    /// result of a read when it's done.
+    pub fn update(&mut self, device: &wgpu::Device) {
+        if self.copy_submitted {
+            self.copy_submitted = false;
+            self.mapping = Some(Box::pin(
+                self.readback_buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read),
+            ));
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+
+        if let Some(mapping) = self.mapping.as_mut() {
+            let waker = noop_waker();
+            let mut context = Context::from_waker(&waker);
+
+            if let Poll::Ready(result) = mapping.as_mut().poll(&mut context) {
+                self.mapping = None;
+
+                if result.is_ok() {
+                    let slice = self.readback_buffer.slice(..);
+                    self.num_collisions = *bytemuck::from_bytes(&slice.get_mapped_range());
+                    self.readback_buffer.unmap();
+                }
+            }
+        }
+    }
+}
+
+/// The mapping is checked on every frame, so nothing needs to be woken up when
+/// it's done.
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    fn noop(_: *const ()) {}
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
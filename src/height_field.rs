@@ -0,0 +1,337 @@
+use primitives::{SceneBounds, Vertex};
+use ultraviolet::{Vec2, Vec3};
+
+/// Where each texel is sampled, in texels from its center. The gpu bake draws
+/// the terrain once for each of these and keeps the highest, so that thin
+/// triangles that miss the center of a texel still count.
+pub const SAMPLE_OFFSETS: [f32; 3] = [-0.5, 0.0, 0.5];
+
+/// A cpu-side copy of the terrain heights, laid out the same way as the baked
+/// height map texture so that both agree on where the ground is.
+pub struct HeightField {
+    bounds: SceneBounds,
+    size: u32,
+    heights: Vec<f32>,
+    min_height: f32,
+    max_height: f32,
+}
+
+impl HeightField {
+    /// Rasterise the terrain triangles from above into `size` by `size`
+    /// texels, which should match the size of the baked height map. Each texel
+    /// stores the highest height at its center, edges and corners. Texels that
+    /// no triangle covers are left at the lowest height, as they are when
+    /// baking on the gpu.
+    pub fn rasterize(vertices: &[Vertex], indices: &[u16], bounds: SceneBounds, size: u32) -> Self {
+        let mut heights = vec![bounds.min_height; (size * size) as usize];
+
+        let extent = bounds.max - bounds.min;
+
+        // Texel centers lie on whole numbers in this space.
+        let to_texel_space = |position: Vec3| {
+            let uv = (Vec2::new(position.x, position.z) - bounds.min) / extent;
+            uv * size as f32 - Vec2::broadcast(0.5)
+        };
+
+        for triangle in indices.chunks_exact(3) {
+            let a = vertices[triangle[0] as usize].position;
+            let b = vertices[triangle[1] as usize].position;
+            let c = vertices[triangle[2] as usize].position;
+
+            let texel_a = to_texel_space(a);
+            let texel_b = to_texel_space(b);
+            let texel_c = to_texel_space(c);
+
+            let area = edge(texel_a, texel_b, texel_c);
+
+            if area.abs() < f32::EPSILON {
+                continue;
+            }
+
+            let min = texel_a.min_by_component(texel_b).min_by_component(texel_c);
+            let max = texel_a.max_by_component(texel_b).max_by_component(texel_c);
+
+            // Texels up to half a texel outside the triangle can be touched
+            // by their samples.
+            let min_x = (min.x - 0.5).ceil().max(0.0) as u32;
+            let min_y = (min.y - 0.5).ceil().max(0.0) as u32;
+            let max_x = ((max.x + 0.5).floor() as i32).min(size as i32 - 1);
+            let max_y = ((max.y + 0.5).floor() as i32).min(size as i32 - 1);
+
+            if max_x < 0 || max_y < 0 {
+                continue;
+            }
+
+            for y in min_y..=max_y as u32 {
+                for x in min_x..=max_x as u32 {
+                    for &offset_y in &SAMPLE_OFFSETS {
+                        for &offset_x in &SAMPLE_OFFSETS {
+                            let point = Vec2::new(x as f32 + offset_x, y as f32 + offset_y);
+
+                            let weight_a = edge(texel_b, texel_c, point) / area;
+                            let weight_b = edge(texel_c, texel_a, point) / area;
+                            let weight_c = edge(texel_a, texel_b, point) / area;
+
+                            let tolerance = -1.0e-5;
+
+                            if weight_a < tolerance || weight_b < tolerance || weight_c < tolerance
+                            {
+                                continue;
+                            }
+
+                            // Keep the highest surface where triangles or samples overlap.
+                            let height = &mut heights[(y * size + x) as usize];
+                            *height = height.max(a.y * weight_a + b.y * weight_b + c.y * weight_c);
+                        }
+                    }
+                }
+            }
+        }
+
+        let min_height = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max_height = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+
+        Self {
+            bounds,
+            size,
+            heights,
+            min_height,
+            max_height,
+        }
+    }
+
+    /// The number of texels along each side.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The height at the center of each texel, row by row.
+    #[cfg(test)]
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// The height of the ground at a world space position, bilinearly filtered
+    /// and clamped to the edges of the terrain.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let uv = (Vec2::new(x, z) - self.bounds.min) / (self.bounds.max - self.bounds.min);
+        let texel = uv * self.size as f32 - Vec2::broadcast(0.5);
+
+        let max = (self.size - 1) as f32;
+        let texel = Vec2::new(texel.x.max(0.0).min(max), texel.y.max(0.0).min(max));
+
+        let x_0 = texel.x.floor() as u32;
+        let y_0 = texel.y.floor() as u32;
+        let x_1 = (x_0 + 1).min(self.size - 1);
+        let y_1 = (y_0 + 1).min(self.size - 1);
+
+        let fract_x = texel.x - x_0 as f32;
+        let fract_y = texel.y - y_0 as f32;
+
+        let height = |x: u32, y: u32| self.heights[(y * self.size + x) as usize];
+
+        let top = height(x_0, y_0) + (height(x_1, y_0) - height(x_0, y_0)) * fract_x;
+        let bottom = height(x_0, y_1) + (height(x_1, y_1) - height(x_0, y_1)) * fract_x;
+
+        top + (bottom - top) * fract_y
+    }
+
+    /// The world space normal of the ground, from the slope between neighbouring texels.
+    pub fn normal_at(&self, x: f32, z: f32) -> Vec3 {
+        let step = (self.bounds.max - self.bounds.min) / self.size as f32;
+
+        let d_x = (self.height_at(x + step.x, z) - self.height_at(x - step.x, z)) / (step.x * 2.0);
+        let d_z = (self.height_at(x, z + step.y) - self.height_at(x, z - step.y)) / (step.y * 2.0);
+
+        Vec3::new(-d_x, 1.0, -d_z).normalized()
+    }
+
+    /// The angle of the ground from flat, in radians, the same as the alpha of
+    /// the baked normal map.
+    pub fn slope_at(&self, x: f32, z: f32) -> f32 {
+        // The normal always points upwards, so only rounding can take y past 1.
+        self.normal_at(x, z).y.min(1.0).acos()
+    }
+
+    /// The first point where a ray hits the ground, if it does so within the
+    /// bounds of the terrain.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3) -> Option<Vec3> {
+        let direction = direction.normalized();
+
+        let (near, far) = self.ray_box_intersection(origin, direction)?;
+
+        let step = (self.bounds.max - self.bounds.min).component_min() / self.size as f32 * 0.5;
+
+        let above_ground = |distance: f32| {
+            let point = origin + direction * distance;
+            point.y > self.height_at(point.x, point.z)
+        };
+
+        let mut previous = near;
+        let mut distance = near;
+
+        loop {
+            if !above_ground(distance) {
+                // Refine the hit between the last point above the ground and this one.
+                let mut above = previous;
+                let mut below = distance;
+
+                for _ in 0..16 {
+                    let middle = (above + below) / 2.0;
+
+                    if above_ground(middle) {
+                        above = middle;
+                    } else {
+                        below = middle;
+                    }
+                }
+
+                let point = origin + direction * below;
+                return Some(Vec3::new(
+                    point.x,
+                    self.height_at(point.x, point.z),
+                    point.z,
+                ));
+            }
+
+            if distance >= far {
+                return None;
+            }
+
+            // Always end on the far side of the box, so no part of the ray is skipped.
+            previous = distance;
+            distance = (distance + step).min(far);
+        }
+    }
+
+    /// The distances along the ray where it enters and leaves the box that
+    /// contains the terrain. The box is padded vertically so that it still has
+    /// some height over flat terrain.
+    fn ray_box_intersection(&self, origin: Vec3, direction: Vec3) -> Option<(f32, f32)> {
+        let padding = 1.0e-3;
+
+        let min = Vec3::new(
+            self.bounds.min.x,
+            self.min_height - padding,
+            self.bounds.min.y,
+        );
+        let max = Vec3::new(
+            self.bounds.max.x,
+            self.max_height + padding,
+            self.bounds.max.y,
+        );
+
+        let inverse_direction = Vec3::one() / direction;
+
+        let t_0 = (min - origin) * inverse_direction;
+        let t_1 = (max - origin) * inverse_direction;
+
+        let near = t_0.min_by_component(t_1).component_max().max(0.0);
+        let far = t_0.max_by_component(t_1).component_min();
+
+        if near <= far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `point`.
+fn edge(a: Vec2, b: Vec2, point: Vec2) -> f32 {
+    (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    fn bounds() -> SceneBounds {
+        SceneBounds {
+            min: Vec2::new(-1.0, -1.0),
+            max: Vec2::new(1.0, 1.0),
+            min_height: 0.0,
+            max_height: 1.0,
+        }
+    }
+
+    /// A quad over the whole of `bounds()`, with its height given by `height`.
+    fn quad(height: impl Fn(f32, f32) -> f32) -> HeightField {
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Vec3::new(x, height(x, z), z),
+            ..bytemuck::Zeroable::zeroed()
+        };
+
+        let vertices = [
+            vertex(-1.0, -1.0),
+            vertex(1.0, -1.0),
+            vertex(1.0, 1.0),
+            vertex(-1.0, 1.0),
+        ];
+
+        HeightField::rasterize(&vertices, &[0, 1, 2, 0, 2, 3], bounds(), SIZE)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).mag() < 1.0e-4, "{:?} isn't close to {:?}", a, b);
+    }
+
+    #[test]
+    fn flat_quad_has_constant_height_and_up_normal() {
+        let height_field = quad(|_, _| 0.5);
+
+        assert!(height_field.heights().iter().all(|&height| height == 0.5));
+
+        for &(x, z) in &[(0.0, 0.0), (-0.9, 0.3), (0.99, -0.99), (2.0, 2.0)] {
+            assert_eq!(height_field.height_at(x, z), 0.5);
+            assert_close(height_field.normal_at(x, z), Vec3::unit_y());
+        }
+    }
+
+    #[test]
+    fn sloped_plane_has_analytic_normal() {
+        let height_field = quad(|x, z| 0.5 + x * 0.25 - z * 0.125);
+
+        let normal = Vec3::new(-0.25, 1.0, 0.125).normalized();
+
+        for &(x, z) in &[(0.0, 0.0), (-0.5, 0.25), (0.5, -0.5)] {
+            assert_close(height_field.normal_at(x, z), normal);
+        }
+    }
+
+    #[test]
+    fn raycast_hits_the_ground() {
+        let height_field = quad(|_, _| 0.5);
+
+        let hit = height_field.raycast(Vec3::new(-1.0, 2.0, 0.25), Vec3::new(1.0, -1.0, 0.0));
+
+        assert_close(hit.unwrap(), Vec3::new(0.5, 0.5, 0.25));
+    }
+
+    #[test]
+    fn raycast_misses_the_ground() {
+        let height_field = quad(|_, _| 0.5);
+
+        // Away from the terrain, and over it but pointing upwards.
+        assert_eq!(
+            height_field.raycast(Vec3::new(-2.0, 2.0, 0.0), Vec3::new(-1.0, -1.0, 0.0)),
+            None
+        );
+        assert_eq!(
+            height_field.raycast(Vec3::new(0.0, 2.0, 0.0), Vec3::unit_y()),
+            None
+        );
+    }
+
+    #[test]
+    fn raycast_from_below_the_ground_hits_where_it_starts() {
+        let height_field = quad(|x, _| 0.5 + x * 0.25);
+
+        let hit = height_field.raycast(Vec3::new(0.0, 0.4, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let height = height_field.height_at(0.0, 0.0);
+        assert_close(hit.unwrap(), Vec3::new(0.0, height, 0.0));
+    }
+}
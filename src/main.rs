@@ -1,15 +1,29 @@
+mod collision_counter;
+mod height_field;
 mod model_loading;
+#[cfg(test)]
+mod reference_simulation;
 mod resource_creation;
 mod resources_and_pipelines;
-
-use cascaded_shadow_maps::CascadedShadowMaps;
+mod routes;
+mod sand_simulation;
+mod sand_tracks;
+#[cfg(test)]
+mod simulation_check;
+mod simulation_clock;
+mod terrain_chunks;
+mod terrain_generation;
+mod vehicles;
+
+use cascaded_shadow_maps::{BoundingBox, CascadedShadowMaps};
 use model_loading::Scene;
+use rand::SeedableRng;
 use resource_creation::{
-    create_height_map, create_land_craft, create_ships, create_texture,
-    framebuffer_and_tonemapper_bind_group,
+    create_height_map, create_main_bind_group, create_shadow_map_debug_bind_groups,
+    create_shadow_map_debug_textures, create_texture, framebuffer_and_tonemapper_bind_group,
 };
 use resources_and_pipelines::{Pipelines, RenderResources};
-use ultraviolet::{Vec2, Vec3};
+use ultraviolet::{Vec2, Vec3, Vec4};
 use wgpu::util::DeviceExt;
 
 fn main() -> anyhow::Result<()> {
@@ -34,8 +48,50 @@ fn main() -> anyhow::Result<()> {
 const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 const FRAMEBUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
 const INDEX_FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint16;
+const SHADOW_MAP_DEBUG_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+/// The precisions that the height map can be baked at.
+const HEIGHT_MAP_FORMATS: [wgpu::TextureFormat; 2] =
+    [wgpu::TextureFormat::R16Float, wgpu::TextureFormat::R32Float];
+/// World space normals in rgb and the slope angle in radians in alpha.
+const HEIGHT_MAP_NORMALS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// The texture coordinates of the terrain mesh.
+const HEIGHT_MAP_UVS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+/// The tangents of the terrain mesh, with the handedness of the bitangent in alpha.
+const HEIGHT_MAP_TANGENTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// How many steps the sand simulation takes between re-rendering the cached
+/// terrain shadows, as the sand only moves a little each step.
+const SAND_SHADOW_REFRESH_STEPS: u32 = 30;
+
+/// The seed for everything that's spawned at random, from `--seed <number>` on
+/// the command line or `?seed=<number>` in the url. Runs with the same seed
+/// play out the same way, so one is picked at random and printed if it isn't
+/// given.
+fn seed() -> anyhow::Result<u64> {
+    #[cfg(not(feature = "wasm"))]
+    let seed = std::env::args().skip_while(|arg| arg != "--seed").nth(1);
+
+    #[cfg(feature = "wasm")]
+    let seed = web_sys::window()
+        .and_then(|window| window.location().search().ok())
+        .and_then(|search| {
+            search
+                .trim_start_matches('?')
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("seed=").map(str::to_string))
+        });
+
+    match seed {
+        Some(seed) => seed
+            .parse()
+            .map_err(|error| anyhow::anyhow!("Invalid seed '{}': {}", seed, error)),
+        None => Ok(rand::random()),
+    }
+}
 
 async fn run() -> anyhow::Result<()> {
+    let seed = seed()?;
+    println!("Seed: {}", seed);
+
     let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
 
     let event_loop = winit::event_loop::EventLoop::new();
@@ -82,16 +138,40 @@ async fn run() -> anyhow::Result<()> {
 
     let resources = RenderResources::new(&device);
 
-    let cascaded_shadow_maps = CascadedShadowMaps::new(&device, 1024);
+    let mut shadow_map_size = 1024;
+    let mut cascaded_shadow_maps = CascadedShadowMaps::new(&device, shadow_map_size);
+    let mut shadow_filter_settings = cascaded_shadow_maps.filter_settings();
+
+    let scene_bytes = include_bytes!("../models/dune.glb");
+    let mut height_map_size = 1024;
+    let mut scene = Scene::load(scene_bytes, &device, &queue, &resources, height_map_size)?;
+    println!(
+        "Camera z near: {}, Camera z far: {}",
+        scene.camera_z_near, scene.camera_z_far
+    );
+
+    // Kept around so that we can switch back from generated terrain.
+    let authored_terrain = model_loading::Terrain::load(scene_bytes)?;
+    let mut generate_terrain = false;
+    let mut terrain_params = terrain_generation::TerrainParams::default();
+    // The chunked terrain streams in the generated dunes past the scene bounds.
+    let mut dune_heights = None;
 
     let mut settings = primitives::Settings {
-        base_colour: Vec3::new(0.8, 0.535, 0.297),
-        detail_map_scale: 1.5,
         ambient_lighting: Vec3::broadcast(0.024),
-        roughness: 0.207,
         mode: primitives::Mode::Full as u32,
         specular_factor: 1.0,
-        ship_movement_bounds: 2.5,
+        ship_movement_bounds: scene.xz_bounds().max_ship_movement_bounds(),
+        wind_direction: std::f32::consts::FRAC_PI_4,
+        wind_strength: 1.0,
+        flocking: 0,
+        boid_neighbour_radius: 0.1,
+        boid_separation_weight: 1.5,
+        boid_alignment_weight: 1.0,
+        boid_cohesion_weight: 1.0,
+        ship_clearance: 0.2,
+        land_craft_avoidance: 1,
+        land_craft_radius: 0.03,
     };
 
     let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -100,6 +180,18 @@ async fn run() -> anyhow::Result<()> {
         contents: bytemuck::bytes_of(&settings),
     });
 
+    let mut terrain_material = primitives::TerrainMaterial::default();
+
+    if scene.has_splat_map {
+        terrain_material.blend_mode = primitives::TerrainBlendMode::SplatMap as u32;
+    }
+
+    let terrain_material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("terrain material buffer"),
+        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        contents: bytemuck::bytes_of(&terrain_material),
+    });
+
     let mut tonemapper_params = TonemapperParams {
         toe: 1.0,
         shoulder: 0.987,
@@ -119,7 +211,7 @@ async fn run() -> anyhow::Result<()> {
         contents: bytemuck::bytes_of(&tonemapper_params.convert()),
     });
 
-    let mut time_since_start = 0.0;
+    let mut clock = simulation_clock::SimulationClock::new();
     let time_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("time buffer"),
         usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
@@ -129,19 +221,6 @@ async fn run() -> anyhow::Result<()> {
         }),
     });
 
-    let scene_bytes = include_bytes!("../models/dune.glb");
-    let mut scene = Scene::load(scene_bytes, &device, &queue, &resources)?;
-    println!(
-        "Camera z near: {}, Camera z far: {}",
-        scene.camera_z_near, scene.camera_z_far
-    );
-
-    let ship_bytes = include_bytes!("../models/ship.glb");
-    let ship = model_loading::Ship::load(ship_bytes, &device, &queue, &resources)?;
-
-    let land_craft_bytes = include_bytes!("../models/landcraft.glb");
-    let land_craft = model_loading::LandCraft::load(land_craft_bytes, &device, &queue, &resources)?;
-
     let display_format = adapter.get_swap_chain_preferred_format(&surface);
     let window_size = window.inner_size();
     let width = window_size.width;
@@ -155,37 +234,31 @@ async fn run() -> anyhow::Result<()> {
         contents: bytemuck::bytes_of(&camera),
     });
 
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("bind group"),
-        layout: &resources.main_bgl,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: scene.sun_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: wgpu::BindingResource::Sampler(&resources.sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 3,
-                resource: settings_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 4,
-                resource: time_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 5,
-                resource: wgpu::BindingResource::Sampler(&resources.clamp_sampler),
-            },
-        ],
+    let scene_bounds_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("scene bounds buffer"),
+        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        contents: bytemuck::bytes_of(&scene.xz_bounds()),
     });
 
+    let sand_tracks = sand_tracks::SandTracks::new(&device, &resources);
+    sand_tracks.clear(&queue);
+
+    let mut sand_simulation = sand_simulation::SandSimulation::new(&device);
+    sand_simulation.reset(&queue);
+
+    let bind_group = create_main_bind_group(
+        &device,
+        &resources,
+        &camera_buffer,
+        &scene.sun_buffer,
+        &settings_buffer,
+        &time_buffer,
+        &scene_bounds_buffer,
+        &sand_tracks.view,
+        &sand_simulation.view,
+        &terrain_material_buffer,
+    );
+
     let pipelines = Pipelines::new(&device, display_format, &resources, &cascaded_shadow_maps);
 
     const BACKGROUND: egui::Color32 = egui::Color32::from_rgba_premultiplied(64, 0, 0, 224);
@@ -215,8 +288,29 @@ async fn run() -> anyhow::Result<()> {
             style,
         });
 
+    // egui works out how long each frame took from the time since this.
+    let egui_start_time = instant::Instant::now();
+
     let mut egui_renderpass = egui_wgpu_backend::RenderPass::new(&device, display_format);
 
+    let mut shadow_debug_settings = primitives::ShadowDebugSettings::default();
+
+    let shadow_debug_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("shadow debug buffer"),
+        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        contents: bytemuck::bytes_of(&shadow_debug_settings),
+    });
+
+    let (shadow_map_debug_textures, shadow_map_debug_texture_ids) =
+        create_shadow_map_debug_textures(&device, &mut egui_renderpass, 256);
+
+    let mut shadow_map_debug_bind_groups = create_shadow_map_debug_bind_groups(
+        &device,
+        &resources,
+        cascaded_shadow_maps.textures(),
+        &shadow_debug_buffer,
+    );
+
     let mut swap_chain_descriptor = wgpu::SwapChainDescriptor {
         usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
         format: display_format,
@@ -246,6 +340,7 @@ async fn run() -> anyhow::Result<()> {
         );
 
     let mut cascade_split_lambda = 0.1;
+    let mut tight_cascade_fitting = false;
     let mut split_cascades = cascaded_shadow_maps::calculate_split_cascades(
         scene.camera_z_near,
         scene.camera_z_far,
@@ -263,41 +358,57 @@ async fn run() -> anyhow::Result<()> {
         &queue,
     );
 
-    let height_map_texture = create_height_map(
+    let mut height_map_format = wgpu::TextureFormat::R32Float;
+
+    let mut height_map = create_height_map(
         &device,
         &queue,
         &pipelines,
+        &bind_group,
         &scene.vertices,
         &scene.indices,
         scene.num_indices,
+        scene.xz_bounds(),
+        height_map_size,
+        height_map_format,
     );
 
-    let mut rng = rand::thread_rng();
-
-    let mut num_ships = 200;
-    let (mut ship_bind_group, mut num_exhaust_particles, mut exhaust_particles_bind_group) =
-        create_ships(num_ships, &device, &mut rng, &resources);
-
-    let mut num_land_craft = 400;
-    let (
-        mut land_craft_bind_group,
-        mut num_smoke_particles,
-        mut smoke_particles_bind_group,
-        mut num_sand_particles,
-        mut sand_particles_bind_group,
-    ) = create_land_craft(
-        num_land_craft,
-        &device,
-        &mut rng,
-        &resources,
-        &height_map_texture,
-        &settings,
-    );
+    sand_simulation.set_height_map(&device, &resources, &height_map.texture);
+
+    let mut terrain_chunks = terrain_chunks::TerrainChunks::new(&device);
+    terrain_chunks.set_height_map(&device, &resources, &height_map);
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+
+    let mut collision_counter = collision_counter::CollisionCounter::new(&device);
+
+    let mut vehicles = {
+        let context = vehicles::SpawnContext {
+            device: &device,
+            queue: &queue,
+            resources: &resources,
+            height_field: &scene.height_field,
+            height_map: &height_map.texture,
+            height_map_normals: &height_map.normals,
+            settings: &settings,
+            scene_bounds: scene.xz_bounds(),
+            sand_colour: terrain_material.layers[0].albedo,
+            routes: &scene.routes,
+            collisions_buffer: &collision_counter.buffer,
+        };
+
+        vehicles::VehicleType::load_all()?
+            .into_iter()
+            .map(|vehicle_type| vehicles::Vehicles::new(vehicle_type, &context, &mut rng))
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
 
     let mut render_sun_dir = false;
-    let mut move_vehicles = true;
-    let mut render_ships = true;
-    let mut render_ship_shadows = true;
+    let mut render_cascade_frusta = false;
+    let mut render_shadow_maps = false;
+    let mut simulate_sand = false;
+    let mut sand_steps_since_shadows = 0;
+    let mut render_terrain_chunks = false;
 
     use winit::dpi::*;
     use winit::event::*;
@@ -346,7 +457,7 @@ async fn run() -> anyhow::Result<()> {
                         &mut camera,
                         &camera_buffer,
                         &swap_chain_descriptor,
-                        &cascaded_shadow_maps,
+                        &mut cascaded_shadow_maps,
                         &queue,
                         &scene,
                         split_cascades,
@@ -372,7 +483,7 @@ async fn run() -> anyhow::Result<()> {
                         &mut camera,
                         &camera_buffer,
                         &swap_chain_descriptor,
-                        &cascaded_shadow_maps,
+                        &mut cascaded_shadow_maps,
                         &queue,
                         &scene,
                         split_cascades,
@@ -393,7 +504,7 @@ async fn run() -> anyhow::Result<()> {
                             &mut camera,
                             &camera_buffer,
                             &swap_chain_descriptor,
-                            &cascaded_shadow_maps,
+                            &mut cascaded_shadow_maps,
                             &queue,
                             &scene,
                             split_cascades,
@@ -407,62 +518,135 @@ async fn run() -> anyhow::Result<()> {
             Event::MainEventsCleared => window.request_redraw(),
             Event::RedrawRequested(_) => match swap_chain.get_current_frame() {
                 Ok(frame) => {
-                    let delta_time = if move_vehicles { 1.0 / 60.0 } else { 0.0 };
-                    time_since_start += delta_time;
-                    queue.write_buffer(
-                        &time_buffer,
-                        0,
-                        bytemuck::bytes_of(&primitives::Time {
-                            time_since_start,
-                            delta_time,
-                        }),
-                    );
+                    let steps = clock.advance();
+                    let num_steps = steps.len() as u32;
+
+                    // Each step is submitted separately so that it sees its own time.
+                    for time in steps {
+                        queue.write_buffer(&time_buffer, 0, bytemuck::bytes_of(&time));
+                        collision_counter.reset(&queue);
+
+                        let mut encoder =
+                            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                                label: Some("simulation step encoder"),
+                            });
+
+                        let mut compute_pass =
+                            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                                label: Some("compute pass"),
+                            });
+
+                        for vehicles in &vehicles {
+                            vehicles.move_particles(&mut compute_pass, &pipelines, &bind_group);
+                        }
+
+                        for vehicles in &vehicles {
+                            vehicles.step(&mut compute_pass, &pipelines, &bind_group, &settings);
+                        }
 
-                    egui_platform.update_time(1.0 / 60.0);
+                        drop(compute_pass);
+
+                        if simulate_sand {
+                            sand_simulation.update(&mut encoder, &pipelines, &bind_group);
+                        }
+
+                        sand_tracks.update(
+                            &mut encoder,
+                            &pipelines,
+                            &bind_group,
+                            vehicles.iter().filter_map(|vehicles| vehicles.tracks()),
+                        );
+
+                        collision_counter.copy(&mut encoder);
+
+                        queue.submit(Some(encoder.finish()));
+                    }
+
+                    collision_counter.update(&device);
+
+                    egui_platform.update_time(egui_start_time.elapsed().as_secs_f64());
+
+                    if render_terrain_chunks {
+                        let heights: &dyn terrain_chunks::HeightSource = match &dune_heights {
+                            Some(dune_heights) => dune_heights,
+                            None => &scene.height_field,
+                        };
+
+                        terrain_chunks.update(&queue, camera.position, scene.xz_bounds(), heights);
+                    }
 
                     let mut encoder =
                         device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
                             label: Some("render encoder"),
                         });
 
-                    let mut compute_pass =
-                        encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                            label: Some("compute pass"),
-                        });
-
-                    compute_pass.set_pipeline(&pipelines.particles_movement_pipeline);
-                    compute_pass.set_bind_group(0, &bind_group, &[]);
-
-                    compute_pass.set_bind_group(1, &exhaust_particles_bind_group, &[]);
-                    compute_pass.dispatch(dispatch_count(num_exhaust_particles, 64), 1, 1);
+                    let static_labels = [
+                        "near static shadow pass",
+                        "middle static shadow pass",
+                        "far static shadow pass",
+                    ];
+                    // The cached terrain shadows follow the sand simulation, but
+                    // not the tracks, which are too shallow to cast shadows.
+                    if simulate_sand {
+                        sand_steps_since_shadows += num_steps;
+
+                        if sand_steps_since_shadows >= SAND_SHADOW_REFRESH_STEPS {
+                            sand_steps_since_shadows = 0;
+                            cascaded_shadow_maps.invalidate_static_depth();
+                        }
+                    } else if sand_steps_since_shadows > 0 {
+                        // Catch up with the last steps before the simulation stopped.
+                        sand_steps_since_shadows = 0;
+                        cascaded_shadow_maps.invalidate_static_depth();
+                    }
 
-                    compute_pass.set_bind_group(1, &smoke_particles_bind_group, &[]);
-                    compute_pass.dispatch(dispatch_count(num_smoke_particles, 64), 1, 1);
+                    let static_cascades_to_render =
+                        cascaded_shadow_maps.static_cascades_to_render();
+                    let static_shadow_textures = cascaded_shadow_maps.static_textures();
+                    let light_projection_bind_groups =
+                        cascaded_shadow_maps.light_projection_bind_groups();
 
-                    compute_pass.set_bind_group(1, &sand_particles_bind_group, &[]);
-                    compute_pass.dispatch(dispatch_count(num_sand_particles, 64), 1, 1);
+                    // Otherwise the terrain only needs to be rendered when the
+                    // cascade it's rendered into changes.
+                    for i in 0..3 {
+                        if !static_cascades_to_render[i] {
+                            continue;
+                        }
 
-                    if move_vehicles {
-                        compute_pass.set_pipeline(&pipelines.land_craft_movement_pipeline);
-                        compute_pass.set_bind_group(0, &bind_group, &[]);
-                        compute_pass.set_bind_group(1, &land_craft_bind_group, &[]);
-                        compute_pass.set_bind_group(2, &smoke_particles_bind_group, &[]);
-                        compute_pass.set_bind_group(3, &sand_particles_bind_group, &[]);
-                        compute_pass.dispatch(dispatch_count(num_land_craft, 64), 1, 1);
+                        let mut render_pass =
+                            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                                label: Some(static_labels[i]),
+                                color_attachments: &[],
+                                depth_stencil_attachment: Some(
+                                    wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                                        attachment: &static_shadow_textures[i],
+                                        depth_ops: Some(wgpu::Operations {
+                                            load: wgpu::LoadOp::Clear(1.0),
+                                            store: true,
+                                        }),
+                                        stencil_ops: None,
+                                    },
+                                ),
+                            });
 
-                        compute_pass.set_pipeline(&pipelines.ship_movement_pipeline);
-                        compute_pass.set_bind_group(0, &bind_group, &[]);
-                        compute_pass.set_bind_group(1, &ship_bind_group, &[]);
-                        compute_pass.set_bind_group(2, &exhaust_particles_bind_group, &[]);
-                        compute_pass.dispatch(dispatch_count(num_ships, 64), 1, 1);
+                        if render_terrain_chunks {
+                            render_pass.set_pipeline(&pipelines.terrain_chunks_shadows_pipeline);
+                            render_pass.set_bind_group(0, &light_projection_bind_groups[i], &[]);
+                            render_pass.set_bind_group(1, &bind_group, &[]);
+                            render_pass.set_bind_group(2, terrain_chunks.bind_group(), &[]);
+                            terrain_chunks.render(&mut render_pass);
+                        } else {
+                            render_pass.set_pipeline(&pipelines.scene_shadows_pipeline);
+                            render_pass.set_bind_group(0, &light_projection_bind_groups[i], &[]);
+                            render_pass.set_bind_group(1, &bind_group, &[]);
+                            render_pass.set_vertex_buffer(0, scene.vertices.slice(..));
+                            render_pass.set_index_buffer(scene.indices.slice(..), INDEX_FORMAT);
+                            render_pass.draw_indexed(0..scene.num_indices, 0, 0..1);
+                        }
                     }
 
-                    drop(compute_pass);
-
                     let labels = ["near shadow pass", "middle shadow pass", "far shadow pass"];
                     let shadow_textures = cascaded_shadow_maps.textures();
-                    let light_projection_bind_groups =
-                        cascaded_shadow_maps.light_projection_bind_groups();
 
                     for i in 0..3 {
                         let mut render_pass =
@@ -481,27 +665,47 @@ async fn run() -> anyhow::Result<()> {
                                 ),
                             });
 
-                        if render_ship_shadows {
-                            render_pass.set_pipeline(&pipelines.ship_shadows_pipeline);
-                            render_pass.set_bind_group(0, &light_projection_bind_groups[i], &[]);
-                            render_pass.set_bind_group(1, &ship_bind_group, &[]);
-                            render_pass.set_vertex_buffer(0, ship.vertices.slice(..));
-                            render_pass.set_index_buffer(ship.indices.slice(..), INDEX_FORMAT);
-                            render_pass.draw_indexed(0..ship.num_indices, 0, 0..num_ships);
-                        }
+                        cascaded_shadow_maps.copy_static_depth(&mut render_pass, i);
 
-                        render_pass.set_pipeline(&pipelines.land_craft_shadows_pipeline);
-                        render_pass.set_bind_group(0, &light_projection_bind_groups[i], &[]);
-                        render_pass.set_bind_group(1, &land_craft_bind_group, &[]);
-                        render_pass.set_vertex_buffer(0, land_craft.vertices.slice(..));
-                        render_pass.set_index_buffer(land_craft.indices.slice(..), INDEX_FORMAT);
-                        render_pass.draw_indexed(0..land_craft.num_indices, 0, 0..num_land_craft);
+                        for vehicles in &vehicles {
+                            vehicles.render_shadows(
+                                &mut render_pass,
+                                &pipelines,
+                                &light_projection_bind_groups[i],
+                            );
+                        }
+                    }
 
-                        render_pass.set_pipeline(&pipelines.scene_shadows_pipeline);
-                        render_pass.set_bind_group(0, &light_projection_bind_groups[i], &[]);
-                        render_pass.set_vertex_buffer(0, scene.vertices.slice(..));
-                        render_pass.set_index_buffer(scene.indices.slice(..), INDEX_FORMAT);
-                        render_pass.draw_indexed(0..scene.num_indices, 0, 0..1);
+                    cascaded_shadow_maps.filter(&mut encoder);
+
+                    if render_shadow_maps {
+                        let labels = [
+                            "near shadow map debug pass",
+                            "middle shadow map debug pass",
+                            "far shadow map debug pass",
+                        ];
+
+                        for i in 0..3 {
+                            let mut render_pass =
+                                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                                    label: Some(labels[i]),
+                                    color_attachments: &[
+                                        wgpu::RenderPassColorAttachmentDescriptor {
+                                            attachment: &shadow_map_debug_textures[i],
+                                            resolve_target: None,
+                                            ops: wgpu::Operations {
+                                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                                                store: true,
+                                            },
+                                        },
+                                    ],
+                                    depth_stencil_attachment: None,
+                                });
+
+                            render_pass.set_pipeline(&pipelines.shadow_map_debug_pipeline);
+                            render_pass.set_bind_group(0, &shadow_map_debug_bind_groups[i], &[]);
+                            render_pass.draw(0..3, 0..1);
+                        }
                     }
 
                     let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -526,49 +730,43 @@ async fn run() -> anyhow::Result<()> {
                         ),
                     });
 
-                    if render_ships {
-                        render_pass.set_pipeline(&pipelines.ship_pipeline);
+                    for vehicles in &vehicles {
+                        vehicles.render(
+                            &mut render_pass,
+                            &pipelines,
+                            &bind_group,
+                            cascaded_shadow_maps.rendering_bind_group(),
+                        );
+                    }
+
+                    if render_terrain_chunks {
+                        render_pass.set_pipeline(&pipelines.terrain_chunks_pipeline);
+                        render_pass.set_bind_group(0, &bind_group, &[]);
+                        render_pass.set_bind_group(1, &scene.texture_bind_group, &[]);
+                        render_pass.set_bind_group(
+                            2,
+                            cascaded_shadow_maps.rendering_bind_group(),
+                            &[],
+                        );
+                        render_pass.set_bind_group(3, terrain_chunks.bind_group(), &[]);
+                        terrain_chunks.render(&mut render_pass);
+                    } else {
+                        render_pass.set_pipeline(&pipelines.scene_pipeline);
                         render_pass.set_bind_group(0, &bind_group, &[]);
-                        render_pass.set_bind_group(1, &ship_bind_group, &[]);
-                        render_pass.set_bind_group(2, &ship.texture_bind_group, &[]);
+                        render_pass.set_bind_group(1, &scene.texture_bind_group, &[]);
                         render_pass.set_bind_group(
-                            3,
+                            2,
                             cascaded_shadow_maps.rendering_bind_group(),
                             &[],
                         );
-                        render_pass.set_vertex_buffer(0, ship.vertices.slice(..));
-                        render_pass.set_index_buffer(ship.indices.slice(..), INDEX_FORMAT);
-                        render_pass.draw_indexed(0..ship.num_indices, 0, 0..num_ships);
+                        render_pass.set_vertex_buffer(0, scene.vertices.slice(..));
+                        render_pass.set_index_buffer(scene.indices.slice(..), INDEX_FORMAT);
+                        render_pass.draw_indexed(0..scene.num_indices, 0, 0..1);
                     }
 
-                    render_pass.set_pipeline(&pipelines.land_craft_pipeline);
-                    render_pass.set_bind_group(0, &bind_group, &[]);
-                    render_pass.set_bind_group(1, &land_craft_bind_group, &[]);
-                    render_pass.set_bind_group(2, &land_craft.texture_bind_group, &[]);
-                    render_pass.set_bind_group(3, cascaded_shadow_maps.rendering_bind_group(), &[]);
-                    render_pass.set_vertex_buffer(0, land_craft.vertices.slice(..));
-                    render_pass.set_index_buffer(land_craft.indices.slice(..), INDEX_FORMAT);
-                    render_pass.draw_indexed(0..land_craft.num_indices, 0, 0..num_land_craft);
-
-                    render_pass.set_pipeline(&pipelines.scene_pipeline);
-                    render_pass.set_bind_group(0, &bind_group, &[]);
-                    render_pass.set_bind_group(1, &scene.texture_bind_group, &[]);
-                    render_pass.set_bind_group(2, cascaded_shadow_maps.rendering_bind_group(), &[]);
-                    render_pass.set_vertex_buffer(0, scene.vertices.slice(..));
-                    render_pass.set_index_buffer(scene.indices.slice(..), INDEX_FORMAT);
-                    render_pass.draw_indexed(0..scene.num_indices, 0, 0..1);
-
-                    render_pass.set_pipeline(&pipelines.particles_pipeline);
-                    render_pass.set_bind_group(0, &bind_group, &[]);
-
-                    render_pass.set_bind_group(1, &sand_particles_bind_group, &[]);
-                    render_pass.draw(0..num_sand_particles * 6, 0..1);
-
-                    render_pass.set_bind_group(1, &smoke_particles_bind_group, &[]);
-                    render_pass.draw(0..num_smoke_particles * 6, 0..1);
-
-                    render_pass.set_bind_group(1, &exhaust_particles_bind_group, &[]);
-                    render_pass.draw(0..num_exhaust_particles * 6, 0..1);
+                    for vehicles in &vehicles {
+                        vehicles.render_particles(&mut render_pass, &pipelines, &bind_group);
+                    }
 
                     drop(render_pass);
 
@@ -591,7 +789,7 @@ async fn run() -> anyhow::Result<()> {
 
                     drop(render_pass);
 
-                    if render_sun_dir {
+                    if render_sun_dir || render_cascade_frusta {
                         let mut render_pass =
                             encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                                 label: Some("lines render pass"),
@@ -615,26 +813,76 @@ async fn run() -> anyhow::Result<()> {
                                 ),
                             });
 
-                        render_pass.set_pipeline(&pipelines.sun_dir_pipeline);
-                        render_pass.set_bind_group(0, &bind_group, &[]);
-                        render_pass.draw(0..2, 0..1);
+                        if render_sun_dir {
+                            render_pass.set_pipeline(&pipelines.sun_dir_pipeline);
+                            render_pass.set_bind_group(0, &bind_group, &[]);
+                            render_pass.draw(0..2, 0..1);
+                        }
+
+                        if render_cascade_frusta {
+                            render_pass.set_pipeline(&pipelines.cascade_frusta_pipeline);
+                            render_pass.set_bind_group(0, &bind_group, &[]);
+
+                            // The instance index picks the colour of the cascade.
+                            for (i, light_projection_bind_group) in cascaded_shadow_maps
+                                .light_projection_bind_groups()
+                                .iter()
+                                .enumerate()
+                            {
+                                let i = i as u32;
+                                render_pass.set_bind_group(1, light_projection_bind_group, &[]);
+                                render_pass.draw(0..24, i..i + 1);
+                            }
+                        }
                     }
 
+                    let cursor_ground = {
+                        let window_size =
+                            window.inner_size().to_logical::<f32>(window.scale_factor());
+                        let (origin, direction) = cursor_ray(
+                            &camera,
+                            previous_cursor_position,
+                            Vec2::new(window_size.width, window_size.height),
+                        );
+
+                        scene
+                            .height_field
+                            .raycast(origin, direction)
+                            .map(|point| (point, scene.height_field.normal_at(point.x, point.z)))
+                    };
+
                     egui_platform.begin_frame();
                     egui::containers::Window::new("Controls").show(
                         &egui_platform.context(),
                         |ui| {
-                            let dirty = draw_ui(
+                            let mut dirty = draw_ui(
                                 ui,
                                 &mut settings,
+                                &mut terrain_material,
+                                scene.has_splat_map,
                                 &mut tonemapper_params,
                                 &mut render_sun_dir,
-                                &mut move_vehicles,
-                                &mut render_ships,
-                                &mut render_ship_shadows,
+                                &mut render_cascade_frusta,
+                                &mut render_shadow_maps,
+                                &mut shadow_debug_settings,
+                                shadow_map_debug_texture_ids,
+                                &mut clock,
+                                seed,
+                                &mut simulate_sand,
+                                &mut render_terrain_chunks,
                                 &mut cascade_split_lambda,
-                                &mut num_ships,
-                                &mut num_land_craft,
+                                &mut tight_cascade_fitting,
+                                cascaded_shadow_maps.texel_densities(),
+                                &mut shadow_filter_settings,
+                                &mut shadow_map_size,
+                                &mut vehicles,
+                                collision_counter.num_collisions(),
+                                &mut generate_terrain,
+                                &mut terrain_params,
+                                &mut height_map_size,
+                                &mut height_map_format,
+                                scene.xz_bounds(),
+                                cursor_ground,
                             );
 
                             if dirty.settings {
@@ -645,6 +893,14 @@ async fn run() -> anyhow::Result<()> {
                                 );
                             }
 
+                            if dirty.terrain_material {
+                                queue.write_buffer(
+                                    &terrain_material_buffer,
+                                    0,
+                                    bytemuck::bytes_of(&terrain_material),
+                                );
+                            }
+
                             if dirty.tonemapper {
                                 queue.write_buffer(
                                     &tonemapper_uniform_buffer,
@@ -653,7 +909,106 @@ async fn run() -> anyhow::Result<()> {
                                 );
                             }
 
+                            if dirty.terrain {
+                                if generate_terrain {
+                                    let terrain = terrain_generation::generate(terrain_params);
+                                    scene.set_terrain(&terrain, &device, &queue, &resources);
+                                    dune_heights =
+                                        Some(terrain_generation::DuneHeights::new(terrain_params));
+                                } else {
+                                    scene.set_terrain(
+                                        &authored_terrain,
+                                        &device,
+                                        &queue,
+                                        &resources,
+                                    );
+                                    dune_heights = None;
+                                }
+
+                                if !scene.has_splat_map {
+                                    terrain_material.blend_mode =
+                                        primitives::TerrainBlendMode::Rules as u32;
+
+                                    queue.write_buffer(
+                                        &terrain_material_buffer,
+                                        0,
+                                        bytemuck::bytes_of(&terrain_material),
+                                    );
+                                }
+
+                                let scene_bounds = scene.xz_bounds();
+
+                                queue.write_buffer(
+                                    &scene_bounds_buffer,
+                                    0,
+                                    bytemuck::bytes_of(&scene_bounds),
+                                );
+
+                                settings.ship_movement_bounds = settings
+                                    .ship_movement_bounds
+                                    .min(scene_bounds.max_ship_movement_bounds());
+                                settings.boid_neighbour_radius = settings
+                                    .boid_neighbour_radius
+                                    .min(settings.max_boid_neighbour_radius());
+
+                                queue.write_buffer(
+                                    &settings_buffer,
+                                    0,
+                                    bytemuck::bytes_of(&settings),
+                                );
+
+                                cascaded_shadow_maps.invalidate_static_depth();
+                                sand_tracks.clear(&queue);
+                                sand_simulation.reset(&queue);
+
+                                // The scene bounds have changed, so the vehicles
+                                // need to be spawned over the new terrain.
+                                dirty.height_map = true;
+                                dirty.csm = true;
+                                dirty.vehicles = true;
+                            }
+
+                            if dirty.terrain_renderer {
+                                cascaded_shadow_maps.invalidate_static_depth();
+                            }
+
+                            if dirty.sand_simulation {
+                                sand_simulation.reset(&queue);
+                                cascaded_shadow_maps.invalidate_static_depth();
+                            }
+
+                            if dirty.height_map {
+                                scene.set_height_field_size(height_map_size);
+
+                                height_map = create_height_map(
+                                    &device,
+                                    &queue,
+                                    &pipelines,
+                                    &bind_group,
+                                    &scene.vertices,
+                                    &scene.indices,
+                                    scene.num_indices,
+                                    scene.xz_bounds(),
+                                    height_map_size,
+                                    height_map_format,
+                                );
+
+                                sand_simulation.set_height_map(
+                                    &device,
+                                    &resources,
+                                    &height_map.texture,
+                                );
+
+                                terrain_chunks.set_height_map(&device, &resources, &height_map);
+                            }
+
                             if dirty.csm {
+                                cascaded_shadow_maps.set_scene_bounds(if tight_cascade_fitting {
+                                    Some(shadow_bounds(&scene, &vehicles, &settings))
+                                } else {
+                                    None
+                                });
+
                                 split_cascades = cascaded_shadow_maps::calculate_split_cascades(
                                     scene.camera_z_near,
                                     scene.camera_z_far,
@@ -671,39 +1026,63 @@ async fn run() -> anyhow::Result<()> {
                                 );
                             };
 
-                            if dirty.ships {
-                                let (
-                                    new_ship_bind_group,
-                                    new_num_exhaust_particles,
-                                    new_exhaust_particles_bind_group,
-                                ) = create_ships(num_ships, &device, &mut rng, &resources);
+                            let mut shadow_textures_recreated = false;
+
+                            if dirty.shadow_map_size {
+                                shadow_textures_recreated |=
+                                    cascaded_shadow_maps.resize(&device, shadow_map_size);
+                            }
 
-                                ship_bind_group = new_ship_bind_group;
-                                num_exhaust_particles = new_num_exhaust_particles;
-                                exhaust_particles_bind_group = new_exhaust_particles_bind_group;
+                            if dirty.shadow_filtering {
+                                shadow_textures_recreated |= cascaded_shadow_maps
+                                    .set_filter_settings(shadow_filter_settings, &device, &queue);
                             }
 
-                            if dirty.landcrafts {
-                                let (
-                                    new_land_craft_bind_group,
-                                    new_num_smoke_particles,
-                                    new_smoke_particles_bind_group,
-                                    new_num_sand_particles,
-                                    new_sand_particles_bind_group,
-                                ) = create_land_craft(
-                                    num_land_craft,
+                            if shadow_textures_recreated {
+                                shadow_map_debug_bind_groups = create_shadow_map_debug_bind_groups(
                                     &device,
-                                    &mut rng,
                                     &resources,
-                                    &height_map_texture,
-                                    &settings,
+                                    cascaded_shadow_maps.textures(),
+                                    &shadow_debug_buffer,
+                                );
+                            }
+
+                            if dirty.shadow_debug {
+                                queue.write_buffer(
+                                    &shadow_debug_buffer,
+                                    0,
+                                    bytemuck::bytes_of(&shadow_debug_settings),
                                 );
+                            }
 
-                                land_craft_bind_group = new_land_craft_bind_group;
-                                num_smoke_particles = new_num_smoke_particles;
-                                smoke_particles_bind_group = new_smoke_particles_bind_group;
-                                num_sand_particles = new_num_sand_particles;
-                                sand_particles_bind_group = new_sand_particles_bind_group;
+                            if dirty.vehicles || dirty.num_vehicles || dirty.height_map {
+                                let context = vehicles::SpawnContext {
+                                    device: &device,
+                                    queue: &queue,
+                                    resources: &resources,
+                                    height_field: &scene.height_field,
+                                    height_map: &height_map.texture,
+                                    height_map_normals: &height_map.normals,
+                                    settings: &settings,
+                                    scene_bounds: scene.xz_bounds(),
+                                    sand_colour: terrain_material.layers[0].albedo,
+                                    routes: &scene.routes,
+                                    collisions_buffer: &collision_counter.buffer,
+                                };
+
+                                for vehicles in &mut vehicles {
+                                    // The movement bind groups reference the height
+                                    // map, which doesn't move the vehicles.
+                                    if dirty.height_map {
+                                        vehicles.set_height_map(&context);
+                                    }
+
+                                    // Only respawn all of the vehicles when they need
+                                    // to be moved, not when the number of them changes.
+                                    if dirty.vehicles || dirty.num_vehicles {
+                                        vehicles.respawn(!dirty.vehicles, &context, &mut rng);
+                                    }
+                                }
                             }
                         },
                     );
@@ -788,28 +1167,36 @@ impl TonemapperParams {
 fn draw_ui(
     ui: &mut egui::Ui,
     settings: &mut primitives::Settings,
+    terrain_material: &mut primitives::TerrainMaterial,
+    has_splat_map: bool,
     tonemapper_params: &mut TonemapperParams,
     render_sun_dir: &mut bool,
-    move_vehicles: &mut bool,
-    render_ships: &mut bool,
-    render_ship_shadows: &mut bool,
+    render_cascade_frusta: &mut bool,
+    render_shadow_maps: &mut bool,
+    shadow_debug_settings: &mut primitives::ShadowDebugSettings,
+    shadow_map_debug_texture_ids: [egui::TextureId; 3],
+    clock: &mut simulation_clock::SimulationClock,
+    seed: u64,
+    simulate_sand: &mut bool,
+    render_terrain_chunks: &mut bool,
     cascade_split_lambda: &mut f32,
-    num_ships: &mut u32,
-    num_land_craft: &mut u32,
+    tight_cascade_fitting: &mut bool,
+    texel_densities: [f32; 3],
+    shadow_filter_settings: &mut cascaded_shadow_maps::FilterSettings,
+    shadow_map_size: &mut u32,
+    vehicles: &mut [vehicles::Vehicles],
+    num_land_craft_collisions: u32,
+    generate_terrain: &mut bool,
+    terrain_params: &mut terrain_generation::TerrainParams,
+    height_map_size: &mut u32,
+    height_map_format: &mut wgpu::TextureFormat,
+    scene_bounds: primitives::SceneBounds,
+    cursor_ground: Option<(Vec3, Vec3)>,
 ) -> DirtyObjects {
     let mut dirty = DirtyObjects::default();
 
     use egui::widgets::color_picker::{color_edit_button_hsva, Alpha};
 
-    let mut base_colour = egui::color::Hsva::from_rgb(settings.base_colour.into());
-
-    ui.label("Ground Colour");
-
-    if color_edit_button_hsva(ui, &mut base_colour, Alpha::Opaque).changed() {
-        settings.base_colour = base_colour.to_rgb().into();
-        dirty.settings = true;
-    }
-
     let mut ambient_lighting = egui::color::Hsva::from_rgb(settings.ambient_lighting.into());
 
     ui.label("Ambient Lighting");
@@ -821,43 +1208,305 @@ fn draw_ui(
 
     dirty.settings |= ui
         .add(
-            egui::widgets::Slider::f32(&mut settings.detail_map_scale, 0.0..=10.0)
-                .text("Detail Map Scale"),
+            egui::widgets::Slider::f32(&mut settings.specular_factor, 0.0..=2.0)
+                .text("Ground Specular Multiplier"),
         )
         .changed();
 
-    dirty.settings |= ui
-        .add(
-            egui::widgets::Slider::f32(&mut settings.roughness, 0.0..=1.0)
-                .text("Ground Specular Roughness"),
+    for (mode, index) in primitives::Mode::iter() {
+        dirty.settings |= ui
+            .radio_value(&mut settings.mode, index, format!("{:?}", mode))
+            .changed();
+    }
+
+    for (mode, index) in primitives::TerrainBlendMode::iter() {
+        if index == primitives::TerrainBlendMode::SplatMap as u32 && !has_splat_map {
+            continue;
+        }
+
+        dirty.terrain_material |= ui
+            .radio_value(
+                &mut terrain_material.blend_mode,
+                index,
+                format!("Blend Terrain By {:?}", mode),
+            )
+            .changed();
+    }
+
+    for (projection, index) in primitives::DetailProjection::iter() {
+        dirty.terrain_material |= ui
+            .radio_value(
+                &mut terrain_material.detail_projection,
+                index,
+                format!("{:?} Detail Projection", projection),
+            )
+            .changed();
+    }
+
+    let blend_by_rules = terrain_material.blend_mode == primitives::TerrainBlendMode::Rules as u32;
+
+    if blend_by_rules {
+        dirty.terrain_material |= ui
+            .add(
+                egui::widgets::Slider::f32(&mut terrain_material.blend_softness, 0.0..=0.5)
+                    .text("Terrain Blend Softness"),
+            )
+            .changed();
+    }
+
+    for (layer, name) in terrain_material
+        .layers
+        .iter_mut()
+        .zip(primitives::TERRAIN_LAYER_NAMES.iter())
+    {
+        ui.collapsing(*name, |ui| {
+            let mut albedo = egui::color::Hsva::from_rgb(layer.albedo.into());
+
+            ui.label("Albedo");
+
+            if color_edit_button_hsva(ui, &mut albedo, Alpha::Opaque).changed() {
+                layer.albedo = albedo.to_rgb().into();
+                dirty.terrain_material = true;
+            }
+
+            dirty.terrain_material |= ui
+                .add(egui::widgets::Slider::f32(&mut layer.roughness, 0.0..=1.0).text("Roughness"))
+                .changed();
+
+            dirty.terrain_material |= ui
+                .add(
+                    egui::widgets::Slider::f32(&mut layer.detail_scale, 0.0..=10.0)
+                        .text("Detail Map Scale"),
+                )
+                .changed();
+
+            dirty.terrain_material |= ui
+                .add(
+                    egui::widgets::Slider::f32(&mut layer.normal_strength, 0.0..=1.0)
+                        .text("Normal Strength"),
+                )
+                .changed();
+
+            if blend_by_rules {
+                let max_slope = std::f32::consts::FRAC_PI_2;
+
+                dirty.terrain_material |= ui
+                    .add(
+                        egui::widgets::Slider::f32(&mut layer.slope_range.x, 0.0..=max_slope)
+                            .text("Min Slope"),
+                    )
+                    .changed();
+
+                dirty.terrain_material |= ui
+                    .add(
+                        egui::widgets::Slider::f32(&mut layer.slope_range.y, 0.0..=max_slope)
+                            .text("Max Slope"),
+                    )
+                    .changed();
+
+                dirty.terrain_material |= ui
+                    .add(
+                        egui::widgets::Slider::f32(&mut layer.height_range.x, 0.0..=1.0)
+                            .text("Min Height"),
+                    )
+                    .changed();
+
+                dirty.terrain_material |= ui
+                    .add(
+                        egui::widgets::Slider::f32(&mut layer.height_range.y, 0.0..=1.0)
+                            .text("Max Height"),
+                    )
+                    .changed();
+            }
+        });
+    }
+
+    for vehicles in vehicles.iter_mut() {
+        let max_count = vehicles.vehicle_type.spawn.max_count;
+        let text = format!("Number Of {}", vehicles.vehicle_type.name);
+
+        dirty.num_vehicles |= ui
+            .add(egui::widgets::Slider::u32(&mut vehicles.num, 1..=max_count).text(text))
+            .changed();
+    }
+
+    let mut flocking = settings.flocking != 0;
+
+    if ui
+        .checkbox(&mut flocking, "Flying Vehicles Flock Together")
+        .changed()
+    {
+        settings.flocking = flocking as u32;
+        dirty.settings = true;
+    }
+
+    if flocking {
+        let max_radius = settings.max_boid_neighbour_radius().min(0.5);
+
+        dirty.settings |= ui
+            .add(
+                egui::widgets::Slider::f32(
+                    &mut settings.boid_neighbour_radius,
+                    max_radius.min(0.02)..=max_radius,
+                )
+                .text("Flocking Neighbour Radius"),
+            )
+            .changed();
+
+        dirty.settings |= ui
+            .add(
+                egui::widgets::Slider::f32(&mut settings.boid_separation_weight, 0.0..=5.0)
+                    .text("Flocking Separation"),
+            )
+            .changed();
+
+        dirty.settings |= ui
+            .add(
+                egui::widgets::Slider::f32(&mut settings.boid_alignment_weight, 0.0..=5.0)
+                    .text("Flocking Alignment"),
+            )
+            .changed();
+
+        dirty.settings |= ui
+            .add(
+                egui::widgets::Slider::f32(&mut settings.boid_cohesion_weight, 0.0..=5.0)
+                    .text("Flocking Cohesion"),
+            )
+            .changed();
+    }
+
+    let mut land_craft_avoidance = settings.land_craft_avoidance != 0;
+
+    if ui
+        .checkbox(
+            &mut land_craft_avoidance,
+            "Driving Vehicles Avoid Each Other",
         )
-        .changed();
+        .changed()
+    {
+        settings.land_craft_avoidance = land_craft_avoidance as u32;
+        dirty.settings = true;
+    }
 
     dirty.settings |= ui
         .add(
-            egui::widgets::Slider::f32(&mut settings.specular_factor, 0.0..=2.0)
-                .text("Ground Specular Multiplier"),
+            egui::widgets::Slider::f32(&mut settings.land_craft_radius, 0.01..=0.1)
+                .text("Driving Vehicle Radius"),
         )
         .changed();
 
-    for (mode, index) in primitives::Mode::iter() {
-        dirty.settings |= ui
-            .radio_value(&mut settings.mode, index, format!("{:?}", mode))
+    ui.label(format!(
+        "Driving Vehicle Collisions: {}",
+        num_land_craft_collisions
+    ));
+
+    dirty.terrain |= ui.checkbox(generate_terrain, "Generated Terrain").changed();
+
+    if *generate_terrain {
+        for style in terrain_generation::DuneStyle::iter() {
+            ui.radio_value(
+                &mut terrain_params.style,
+                style,
+                format!("{:?} Dunes", style),
+            );
+        }
+
+        ui.add(
+            egui::widgets::Slider::f32(
+                &mut terrain_params.wind_direction,
+                0.0..=std::f32::consts::PI * 2.0,
+            )
+            .text("Wind Direction"),
+        );
+
+        ui.add(
+            egui::widgets::Slider::f32(&mut terrain_params.wavelength, 0.2..=2.0)
+                .text("Dune Wavelength"),
+        );
+
+        ui.add(
+            egui::widgets::Slider::f32(&mut terrain_params.height, 0.05..=0.5).text("Dune Height"),
+        );
+
+        ui.add(egui::widgets::Slider::u32(&mut terrain_params.seed, 0..=1000).text("Terrain Seed"));
+
+        // Generating the terrain takes a while, so only do it when asked to.
+        dirty.terrain |= ui.button("Generate Terrain").clicked();
+    }
+
+    for &size in &[512, 1024, 2048, 4096] {
+        dirty.height_map |= ui
+            .radio_value(height_map_size, size, format!("Height Map Size {}", size))
+            .changed();
+    }
+
+    for &format in &HEIGHT_MAP_FORMATS {
+        dirty.height_map |= ui
+            .radio_value(
+                height_map_format,
+                format,
+                format!("Height Map Format {:?}", format),
+            )
             .changed();
     }
 
-    dirty.ships |= ui
-        .add(egui::widgets::Slider::u32(num_ships, 1..=2000).text("Number Of Ships"))
+    ui.checkbox(simulate_sand, "Simulate Sand");
+
+    // The terrain is drawn differently into the cached static shadows.
+    dirty.terrain_renderer |= ui
+        .checkbox(render_terrain_chunks, "Chunked Terrain")
         .changed();
 
-    dirty.landcrafts |= ui
-        .add(egui::widgets::Slider::u32(num_land_craft, 1..=2000).text("Number Of Landcrafts"))
+    dirty.settings |= ui
+        .add(
+            egui::widgets::Slider::f32(
+                &mut settings.wind_direction,
+                0.0..=std::f32::consts::PI * 2.0,
+            )
+            .text("Sand Wind Direction"),
+        )
+        .changed();
+
+    dirty.settings |= ui
+        .add(
+            egui::widgets::Slider::f32(&mut settings.wind_strength, 0.0..=5.0)
+                .text("Sand Wind Strength"),
+        )
         .changed();
 
+    dirty.sand_simulation |= ui.button("Reset Sand").clicked();
+
+    match cursor_ground {
+        Some((position, normal)) => ui.label(format!(
+            "Ground Under Cursor: ({:.2}, {:.2}, {:.2}), Slope: {:.0}°",
+            position.x,
+            position.y,
+            position.z,
+            normal.y.acos().to_degrees()
+        )),
+        None => ui.label("Ground Under Cursor: None"),
+    };
+
     ui.checkbox(render_sun_dir, "Render Sun Direction");
-    ui.checkbox(move_vehicles, "Move Vehicles");
-    ui.checkbox(render_ships, "Render Ships");
-    ui.checkbox(render_ship_shadows, "Render Ship Shadows");
+    ui.checkbox(render_cascade_frusta, "Render Cascade Frusta");
+    ui.checkbox(&mut clock.paused, "Pause Simulation");
+
+    if clock.paused && ui.button("Step Simulation").clicked() {
+        clock.request_step();
+    }
+
+    ui.add(egui::widgets::Slider::f32(&mut clock.time_scale, 0.05..=4.0).text("Time Scale"));
+
+    ui.label(format!("Simulation Time: {:.3}s", clock.time_since_start()));
+    ui.label(format!("Seed: {}", seed));
+    for vehicles in vehicles.iter_mut() {
+        let name = &vehicles.vehicle_type.name;
+        ui.checkbox(&mut vehicles.render, format!("Render {}", name));
+        ui.checkbox(
+            &mut vehicles.render_shadows,
+            format!("Render {} Shadows", name),
+        );
+    }
 
     dirty.csm |= ui
         .add(
@@ -866,12 +1515,97 @@ fn draw_ui(
         )
         .changed();
 
-    dirty.settings |= ui
+    dirty.csm |= ui
+        .checkbox(tight_cascade_fitting, "Tight Cascade Fitting")
+        .changed();
+
+    ui.label(format!(
+        "Cascade Texels Per Unit: {:.1}, {:.1}, {:.1}",
+        texel_densities[0], texel_densities[1], texel_densities[2]
+    ));
+
+    for &size in &[512, 1024, 2048, 4096] {
+        dirty.shadow_map_size |= ui
+            .radio_value(shadow_map_size, size, format!("Shadow Map Size {}", size))
+            .changed();
+    }
+
+    for mode in cascaded_shadow_maps::ShadowMode::iter() {
+        dirty.shadow_filtering |= ui
+            .radio_value(
+                &mut shadow_filter_settings.mode,
+                mode,
+                format!("Shadows {:?}", mode),
+            )
+            .changed();
+    }
+
+    dirty.shadow_filtering |= ui
+        .add(
+            egui::widgets::Slider::u32(&mut shadow_filter_settings.blur_radius, 0..=8)
+                .text("Shadow Blur Radius"),
+        )
+        .changed();
+
+    dirty.shadow_filtering |= ui
+        .add(
+            egui::widgets::Slider::f32(
+                &mut shadow_filter_settings.light_bleeding_reduction,
+                0.0..=0.95,
+            )
+            .text("Shadow Light Bleeding Reduction"),
+        )
+        .changed();
+
+    ui.checkbox(render_shadow_maps, "Show Shadow Maps");
+
+    if *render_shadow_maps {
+        dirty.shadow_debug |= ui
+            .add(
+                egui::widgets::Slider::f32(&mut shadow_debug_settings.depth_min, 0.0..=1.0)
+                    .text("Shadow Map Depth Min"),
+            )
+            .changed();
+
+        dirty.shadow_debug |= ui
+            .add(
+                egui::widgets::Slider::f32(&mut shadow_debug_settings.depth_max, 0.0..=1.0)
+                    .text("Shadow Map Depth Max"),
+            )
+            .changed();
+
+        ui.horizontal(|ui| {
+            for &texture_id in &shadow_map_debug_texture_ids {
+                ui.image(texture_id, [128.0, 128.0]);
+            }
+        });
+    }
+
+    let ship_movement_bounds_changed = ui
         .add(
-            egui::widgets::Slider::f32(&mut settings.ship_movement_bounds, 0.0..=2.5)
-                .text("Ship Movement Bounds"),
+            egui::widgets::Slider::f32(
+                &mut settings.ship_movement_bounds,
+                0.0..=scene_bounds.max_ship_movement_bounds(),
+            )
+            .text("Ship Movement Bounds"),
         )
         .changed();
+    let ship_clearance_changed = ui
+        .add(
+            egui::widgets::Slider::f32(&mut settings.ship_clearance, 0.02..=1.0)
+                .text("Ship Clearance"),
+        )
+        .changed();
+
+    if ship_movement_bounds_changed {
+        settings.boid_neighbour_radius = settings
+            .boid_neighbour_radius
+            .min(settings.max_boid_neighbour_radius());
+    }
+
+    // The ships are included in the bounds used for tight cascade fitting.
+    dirty.settings |= ship_movement_bounds_changed | ship_clearance_changed;
+    dirty.csm |= ship_movement_bounds_changed | ship_clearance_changed;
 
     for mode in primitives::TonemapperMode::iter() {
         dirty.tonemapper |= ui
@@ -929,11 +1663,19 @@ struct DirtyObjects {
     settings: bool,
     tonemapper: bool,
     csm: bool,
-    ships: bool,
-    landcrafts: bool,
+    shadow_filtering: bool,
+    shadow_map_size: bool,
+    shadow_debug: bool,
+    terrain_material: bool,
+    terrain: bool,
+    height_map: bool,
+    sand_simulation: bool,
+    terrain_renderer: bool,
+    vehicles: bool,
+    num_vehicles: bool,
 }
 
-const fn dispatch_count(num: u32, group_size: u32) -> u32 {
+pub const fn dispatch_count(num: u32, group_size: u32) -> u32 {
     let mut count = num / group_size;
     let rem = num % group_size;
     if rem != 0 {
@@ -947,7 +1689,7 @@ fn update_camera_and_shadows(
     camera: &mut primitives::Camera,
     camera_buffer: &wgpu::Buffer,
     swap_chain_descriptor: &wgpu::SwapChainDescriptor,
-    cascaded_shadow_maps: &CascadedShadowMaps,
+    cascaded_shadow_maps: &mut CascadedShadowMaps,
     queue: &wgpu::Queue,
     scene: &Scene,
     split_cascades: [f32; 4],
@@ -966,3 +1708,30 @@ fn update_camera_and_shadows(
         &queue,
     );
 }
+
+/// A world space ray going from the camera through the cursor.
+fn cursor_ray(camera: &primitives::Camera, cursor: Vec2, window_size: Vec2) -> (Vec3, Vec3) {
+    let ndc = Vec2::new(
+        cursor.x / window_size.x * 2.0 - 1.0,
+        1.0 - cursor.y / window_size.y * 2.0,
+    );
+
+    let inverse_perspective_view = camera.perspective_view.inversed();
+
+    // The far plane is at a depth of 1 with wgpu's projection.
+    let far = inverse_perspective_view * Vec4::new(ndc.x, ndc.y, 1.0, 1.0);
+    let far = far.xyz() / far.w;
+
+    (camera.position, far - camera.position)
+}
+
+/// The bounds of everything that can cast or receive shadows.
+fn shadow_bounds(
+    scene: &Scene,
+    vehicles: &[vehicles::Vehicles],
+    settings: &primitives::Settings,
+) -> BoundingBox {
+    vehicles.iter().fold(scene.bounds, |bounds, vehicles| {
+        bounds.union(vehicles.bounds(scene, settings))
+    })
+}
@@ -1,6 +1,9 @@
+use crate::height_field::HeightField;
+use crate::routes::Routes;
 use crate::RenderResources;
-use primitives::{Sun, Vec3A, Vertex};
-use std::collections::HashMap;
+use cascaded_shadow_maps::BoundingBox;
+use primitives::{Sun, Vec3A, Vertex, NUM_TERRAIN_LAYERS};
+use std::collections::{BTreeMap, HashMap};
 use ultraviolet::{Mat4, Vec2, Vec3};
 use wgpu::util::DeviceExt;
 
@@ -56,7 +59,15 @@ pub struct Scene {
     pub indices: wgpu::Buffer,
     pub num_indices: u32,
     pub sun_facing: Vec3,
+    pub bounds: BoundingBox,
+    pub height_field: HeightField,
+    pub has_splat_map: bool,
+    pub routes: Routes,
     look_at: Vec3,
+    /// A cpu-side copy of the terrain mesh, for rasterising the height field
+    /// again when the size of the height map changes.
+    terrain_vertices: Vec<Vertex>,
+    terrain_indices: Vec<u16>,
 }
 
 impl Scene {
@@ -65,11 +76,10 @@ impl Scene {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         resources: &RenderResources,
+        height_field_size: u32,
     ) -> anyhow::Result<Self> {
         let gltf = gltf::Gltf::from_slice(bytes)?;
 
-        let buffer_blob = gltf.blob.as_ref().unwrap();
-
         let node_tree = NodeTree::new(&gltf);
 
         let (camera_node_index, camera) = gltf
@@ -94,34 +104,6 @@ impl Scene {
         };
         let orbit = Orbit::from_vector(camera_eye - look_at);
 
-        let mut image_map = HashMap::new();
-
-        for image in gltf.images() {
-            image_map.insert(
-                image.name().unwrap(),
-                load_image(&image, buffer_blob, device, queue)?,
-            );
-        }
-
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("scene texture bind group"),
-            layout: &resources.double_texture_bgl,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(
-                        &image_map.get("normals").unwrap(),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(
-                        &image_map.get("details").unwrap(),
-                    ),
-                },
-            ],
-        });
-
         let (sun_node_index, sun) = gltf
             .nodes()
             .find_map(|node| node.light().map(|light| (node.index(), light)))
@@ -143,6 +125,158 @@ impl Scene {
             contents: bytemuck::bytes_of(&sun),
         });
 
+        let terrain = Terrain::from_gltf(&gltf)?;
+
+        let TerrainResources {
+            texture_bind_group,
+            vertices,
+            indices,
+            num_indices,
+            bounds,
+        } = terrain.upload(device, queue, resources);
+
+        let height_field = terrain.height_field(height_field_size);
+        let has_splat_map = terrain.splat_map.is_some();
+        let routes = terrain.routes(&height_field);
+
+        Ok(Self {
+            camera_y_fov: camera_perspective.yfov(),
+            camera_z_near: camera_perspective.znear(),
+            camera_z_far: camera_perspective.zfar().unwrap() * 1.5,
+            texture_bind_group,
+            sun_buffer,
+            vertices,
+            indices,
+            num_indices,
+            sun_facing,
+            bounds,
+            height_field,
+            has_splat_map,
+            routes,
+            orbit,
+            look_at,
+            terrain_vertices: terrain.vertices,
+            terrain_indices: terrain.indices,
+        })
+    }
+
+    /// Replace the terrain mesh and textures, keeping the camera and sun.
+    pub fn set_terrain(
+        &mut self,
+        terrain: &Terrain,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        resources: &RenderResources,
+    ) {
+        let TerrainResources {
+            texture_bind_group,
+            vertices,
+            indices,
+            num_indices,
+            bounds,
+        } = terrain.upload(device, queue, resources);
+
+        self.texture_bind_group = texture_bind_group;
+        self.vertices = vertices;
+        self.indices = indices;
+        self.num_indices = num_indices;
+        self.bounds = bounds;
+        self.height_field = terrain.height_field(self.height_field.size());
+        self.has_splat_map = terrain.splat_map.is_some();
+        self.routes = terrain.routes(&self.height_field);
+        self.terrain_vertices = terrain.vertices.clone();
+        self.terrain_indices = terrain.indices.clone();
+    }
+
+    /// Rasterise the height field again to match a new size of height map.
+    pub fn set_height_field_size(&mut self, size: u32) {
+        if size == self.height_field.size() {
+            return;
+        }
+
+        self.height_field = HeightField::rasterize(
+            &self.terrain_vertices,
+            &self.terrain_indices,
+            self.xz_bounds(),
+            size,
+        );
+    }
+
+    pub fn xz_bounds(&self) -> primitives::SceneBounds {
+        xz_bounds(self.bounds)
+    }
+
+    pub fn create_camera(&self, width: u32, height: u32) -> primitives::Camera {
+        let camera_eye = self.orbit.as_vector() + self.look_at;
+
+        let camera_view = Mat4::look_at(camera_eye, self.look_at, Vec3::unit_y());
+
+        let perspective = ultraviolet::projection::perspective_wgpu_dx(
+            self.camera_y_fov,
+            width as f32 / height as f32,
+            self.camera_z_near,
+            self.camera_z_far,
+        );
+
+        let perspective_view = perspective * camera_view;
+
+        primitives::Camera {
+            perspective_view,
+            view: camera_view,
+            perspective,
+            position: camera_eye,
+        }
+    }
+}
+
+/// The names of the images in the glb that override the shared detail map for
+/// each terrain layer, in the same order as `primitives::TERRAIN_LAYER_NAMES`.
+const TERRAIN_LAYER_DETAIL_IMAGES: [&str; NUM_TERRAIN_LAYERS] = [
+    "ripple_sand_details",
+    "packed_sand_details",
+    "rock_details",
+    "salt_flat_details",
+];
+
+/// The terrain mesh along with its normal and detail maps, before being uploaded
+/// to the gpu.
+pub struct Terrain {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+    pub normals: image::RgbaImage,
+    pub details: image::RgbaImage,
+    /// Detail maps for individual layers, which need to be the same size as
+    /// `details`. Layers without one use `details`.
+    pub layer_details: [Option<image::RgbaImage>; NUM_TERRAIN_LAYERS],
+    /// The weights of each layer in the rgba channels, over the mesh's uvs.
+    pub splat_map: Option<image::RgbaImage>,
+    /// The waypoints of the routes for the land craft. Routes are generated
+    /// when there aren't any.
+    pub routes: Vec<Vec<Vec2>>,
+}
+
+struct TerrainResources {
+    texture_bind_group: wgpu::BindGroup,
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    num_indices: u32,
+    bounds: BoundingBox,
+}
+
+impl Terrain {
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        Self::from_gltf(&gltf::Gltf::from_slice(bytes)?)
+    }
+
+    fn from_gltf(gltf: &gltf::Gltf) -> anyhow::Result<Self> {
+        let buffer_blob = gltf.blob.as_ref().unwrap();
+
+        let mut image_map = HashMap::new();
+
+        for image in gltf.images() {
+            image_map.insert(image.name().unwrap(), decode_image(&image, buffer_blob)?);
+        }
+
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
@@ -183,64 +317,254 @@ impl Scene {
             }
         }
 
+        let details = image_map.remove("details").unwrap();
+
+        let mut layer_details: [Option<image::RgbaImage>; NUM_TERRAIN_LAYERS] = Default::default();
+
+        for (layer, name) in layer_details
+            .iter_mut()
+            .zip(TERRAIN_LAYER_DETAIL_IMAGES.iter())
+        {
+            *layer = image_map.remove(name);
+
+            if let Some(image) = layer {
+                if image.dimensions() != details.dimensions() {
+                    return Err(anyhow::anyhow!(
+                        "{} is {:?} but needs to match the details image at {:?}",
+                        name,
+                        image.dimensions(),
+                        details.dimensions()
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            vertices,
+            indices,
+            normals: image_map.remove("normals").unwrap(),
+            details,
+            layer_details,
+            splat_map: image_map.remove("splat"),
+            routes: authored_routes(gltf),
+        })
+    }
+
+    fn routes(&self, height_field: &HeightField) -> Routes {
+        let routes = Routes::new(self.routes.clone());
+
+        if routes.is_empty() {
+            Routes::generate(xz_bounds(self.bounds()), height_field)
+        } else {
+            routes
+        }
+    }
+
+    fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        resources: &RenderResources,
+    ) -> TerrainResources {
+        // Normal maps store vectors rather than colours, so they're linear.
+        let normals = create_image_texture(
+            "normals",
+            &self.normals,
+            wgpu::TextureFormat::Rgba8Unorm,
+            device,
+            queue,
+        );
+        let layer_details = self.upload_layer_details(device, queue);
+
+        // Without a splat map, everything is the first layer.
+        let splat_map = match &self.splat_map {
+            Some(splat_map) => splat_map.clone(),
+            None => image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 0])),
+        };
+        let splat_map = create_image_texture(
+            "splat map",
+            &splat_map,
+            wgpu::TextureFormat::Rgba8Unorm,
+            device,
+            queue,
+        );
+
+        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("scene texture bind group"),
+            layout: &resources.terrain_textures_bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&normals),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&layer_details),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&splat_map),
+                },
+            ],
+        });
+
         let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("vertices"),
             usage: wgpu::BufferUsage::VERTEX,
-            contents: bytemuck::cast_slice(&vertices),
+            contents: bytemuck::cast_slice(&self.vertices),
         });
 
-        let num_indices = indices.len() as u32;
-
         let indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("indices"),
             usage: wgpu::BufferUsage::INDEX,
-            contents: bytemuck::cast_slice(&indices),
+            contents: bytemuck::cast_slice(&self.indices),
         });
 
-        Ok(Self {
-            camera_y_fov: camera_perspective.yfov(),
-            camera_z_near: camera_perspective.znear(),
-            camera_z_far: camera_perspective.zfar().unwrap() * 1.5,
+        TerrainResources {
             texture_bind_group,
-            sun_buffer,
             vertices,
             indices,
-            num_indices,
-            sun_facing,
-            orbit,
-            look_at,
+            num_indices: self.indices.len() as u32,
+            bounds: self.bounds(),
+        }
+    }
+
+    /// Upload the detail maps as layers of one texture array.
+    fn upload_layer_details(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> wgpu::TextureView {
+        let (width, height) = self.details.dimensions();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("terrain layer details"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: NUM_TERRAIN_LAYERS as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // The detail maps are normal maps, so they're linear.
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::SAMPLED,
+        });
+
+        for (i, layer) in self.layer_details.iter().enumerate() {
+            let image = layer.as_ref().unwrap_or(&self.details);
+
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: i as u32,
+                    },
+                },
+                image.as_raw(),
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: width * 4,
+                    rows_per_image: 0,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+        }
+
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
         })
     }
 
-    pub fn create_camera(&self, width: u32, height: u32) -> primitives::Camera {
-        let camera_eye = self.orbit.as_vector() + self.look_at;
+    pub fn height_field(&self, size: u32) -> HeightField {
+        HeightField::rasterize(
+            &self.vertices,
+            &self.indices,
+            xz_bounds(self.bounds()),
+            size,
+        )
+    }
 
-        let camera_view = Mat4::look_at(camera_eye, self.look_at, Vec3::unit_y());
+    fn bounds(&self) -> BoundingBox {
+        BoundingBox::from_points(self.vertices.iter().map(|vertex| vertex.position))
+    }
+}
 
-        let perspective = ultraviolet::projection::perspective_wgpu_dx(
-            self.camera_y_fov,
-            width as f32 / height as f32,
-            self.camera_z_near,
-            self.camera_z_far,
-        );
+/// Collect the routes from empties named `route_<route name>_<index>`, which
+/// are visited in order of their index. glTF doesn't support curves, so these
+/// are the points that the spline passes through.
+fn authored_routes(gltf: &gltf::Gltf) -> Vec<Vec<Vec2>> {
+    let node_tree = NodeTree::new(gltf);
 
-        let perspective_view = perspective * camera_view;
+    let mut routes: BTreeMap<&str, Vec<(u32, Vec2)>> = BTreeMap::new();
 
-        primitives::Camera {
-            perspective_view,
-            view: camera_view,
-            perspective,
-            position: camera_eye,
+    for node in gltf.nodes() {
+        if let Some((route, index)) = node.name().and_then(parse_waypoint_name) {
+            let position = node_tree.transform_of(node.index()).extract_translation();
+
+            routes
+                .entry(route)
+                .or_default()
+                .push((index, Vec2::new(position.x, position.z)));
         }
     }
+
+    routes
+        .into_values()
+        .map(|mut waypoints| {
+            waypoints.sort_by_key(|&(index, _)| index);
+            waypoints
+                .into_iter()
+                .map(|(_, position)| position)
+                .collect()
+        })
+        .collect()
+}
+
+fn parse_waypoint_name(name: &str) -> Option<(&str, u32)> {
+    let name = name.strip_prefix("route_")?;
+    let mut parts = name.rsplitn(2, '_');
+    let index = parts.next()?.parse().ok()?;
+    let route = parts.next()?;
+    Some((route, index))
+}
+
+fn xz_bounds(bounds: BoundingBox) -> primitives::SceneBounds {
+    primitives::SceneBounds {
+        min: Vec2::new(bounds.min.x, bounds.min.z),
+        max: Vec2::new(bounds.max.x, bounds.max.z),
+        min_height: bounds.min.y,
+        max_height: bounds.max.y,
+    }
 }
 
+/// Load a colour image, which is stored in sRGB.
 fn load_image(
     image: &gltf::Image,
     buffer_blob: &[u8],
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<wgpu::TextureView> {
+    let decoded = decode_image(image, buffer_blob)?;
+    Ok(create_image_texture(
+        image.name().unwrap(),
+        &decoded,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        device,
+        queue,
+    ))
+}
+
+fn decode_image(image: &gltf::Image, buffer_blob: &[u8]) -> anyhow::Result<image::RgbaImage> {
     let image_view = match image.source() {
         gltf::image::Source::View { view, .. } => view,
         _ => panic!(),
@@ -250,16 +574,22 @@ fn load_image(
     let image_end = image_start + image_view.length();
     let image_bytes = &buffer_blob[image_start..image_end];
 
-    let name = image.name().unwrap();
-
     let image = image::load_from_memory_with_format(image_bytes, image::ImageFormat::Png)?;
 
-    let image = match image {
-        image::DynamicImage::ImageRgba8(image) => image,
+    match image {
+        image::DynamicImage::ImageRgba8(image) => Ok(image),
         _ => panic!(),
-    };
+    }
+}
 
-    Ok(device
+fn create_image_texture(
+    name: &str,
+    image: &image::RgbaImage,
+    format: wgpu::TextureFormat,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> wgpu::TextureView {
+    device
         .create_texture_with_data(
             queue,
             &wgpu::TextureDescriptor {
@@ -272,12 +602,12 @@ fn load_image(
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format,
                 usage: wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::SAMPLED,
             },
-            &*image,
+            image.as_raw(),
         )
-        .create_view(&wgpu::TextureViewDescriptor::default()))
+        .create_view(&wgpu::TextureViewDescriptor::default())
 }
 
 struct NodeTree {
@@ -311,16 +641,20 @@ impl NodeTree {
     }
 }
 
-pub struct Ship {
+/// The model that every vehicle of a type is drawn with.
+pub struct VehicleModel {
     pub vertices: wgpu::Buffer,
     pub indices: wgpu::Buffer,
     pub num_indices: u32,
     pub texture_bind_group: wgpu::BindGroup,
+    pub bounds: BoundingBox,
 }
 
-impl Ship {
+impl VehicleModel {
+    /// `scale` is applied on top of the transforms of the nodes in the model.
     pub fn load(
         bytes: &[u8],
+        scale: f32,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         resources: &RenderResources,
@@ -368,7 +702,7 @@ impl Ship {
                         let position: Vec3 = position.into();
 
                         vertices.push(Vertex {
-                            position: transform.transform_vec3(position),
+                            position: transform.transform_vec3(position) * scale,
                             uv: uv.into(),
                             normal: normal.into(),
                             tangent: tangent.into(),
@@ -378,102 +712,7 @@ impl Ship {
             }
         }
 
-        let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vertices"),
-            usage: wgpu::BufferUsage::VERTEX,
-            contents: bytemuck::cast_slice(&vertices),
-        });
-
-        let num_indices = indices.len() as u32;
-
-        let indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("indices"),
-            usage: wgpu::BufferUsage::INDEX,
-            contents: bytemuck::cast_slice(&indices),
-        });
-
-        let image = gltf.images().next().unwrap();
-        let image = load_image(&image, buffer_blob, device, queue)?;
-
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("ship texture bind group"),
-            layout: &resources.single_texture_bgl,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&image),
-            }],
-        });
-
-        Ok(Self {
-            vertices,
-            indices,
-            num_indices,
-            texture_bind_group,
-        })
-    }
-}
-
-pub struct LandCraft {
-    pub vertices: wgpu::Buffer,
-    pub indices: wgpu::Buffer,
-    pub num_indices: u32,
-    pub texture_bind_group: wgpu::BindGroup,
-}
-
-impl LandCraft {
-    pub fn load(
-        bytes: &[u8],
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        resources: &RenderResources,
-    ) -> anyhow::Result<Self> {
-        let gltf = gltf::Gltf::from_slice(bytes)?;
-
-        let buffer_blob = gltf.blob.as_ref().unwrap();
-
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
-
-        const SIZE: f32 = 0.02;
-
-        for mesh in gltf.meshes() {
-            for primitive in mesh.primitives() {
-                let reader = primitive.reader(|buffer| {
-                    assert_eq!(buffer.index(), 0);
-                    Some(buffer_blob)
-                });
-
-                let num_vertices = vertices.len() as u16;
-
-                let read_indices = match reader.read_indices().unwrap() {
-                    gltf::mesh::util::ReadIndices::U16(indices) => indices,
-                    gltf::mesh::util::ReadIndices::U32(_) => {
-                        return Err(anyhow::anyhow!("U32 indices not supported"))
-                    }
-                    _ => unreachable!(),
-                };
-
-                indices.extend(read_indices.map(|index| index + num_vertices));
-
-                let positions = reader.read_positions().unwrap();
-                let uvs = reader.read_tex_coords(0).unwrap().into_f32();
-                let normals = reader.read_normals().unwrap();
-                let tangents = reader.read_tangents().unwrap();
-
-                positions.zip(uvs).zip(normals).zip(tangents).for_each(
-                    |(((position, uv), normal), tangent)| {
-                        let position: Vec3 = position.into();
-
-                        vertices.push(Vertex {
-                            position: position * SIZE,
-                            uv: uv.into(),
-                            normal: normal.into(),
-                            tangent: tangent.into(),
-                        });
-                    },
-                )
-            }
-        }
+        let bounds = BoundingBox::from_points(vertices.iter().map(|vertex| vertex.position));
 
         let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("vertices"),
@@ -493,7 +732,7 @@ impl LandCraft {
         let image = load_image(&image, buffer_blob, device, queue)?;
 
         let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("ship texture bind group"),
+            label: Some("vehicle texture bind group"),
             layout: &resources.single_texture_bgl,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -506,6 +745,7 @@ impl LandCraft {
             indices,
             num_indices,
             texture_bind_group,
+            bounds,
         })
     }
 }
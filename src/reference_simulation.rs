@@ -0,0 +1,324 @@
+use crate::height_field::HeightField;
+use crate::routes::Routes;
+use primitives::{LandCraft, Particle, SceneBounds, Settings, Ship, Time};
+use std::f32::consts::PI;
+use ultraviolet::{Vec2, Vec3, Vec4};
+
+// These need to match the constants in `ship/movement.comp`.
+const SHIP_SPEED: f32 = 0.3;
+const MAX_FLOCKING_TURN_RATE: f32 = 3.0;
+const FLOCKING_WANDER: f32 = 0.25;
+const ALTITUDE_LOOK_AHEAD: f32 = 0.5;
+const ALTITUDE_RESPONSE: f32 = 3.0;
+const BANK_RESPONSE: f32 = 4.0;
+const BANK_PER_TURN_RATE: f32 = 0.4;
+const MAX_BANK: f32 = 0.8;
+const BOB_FREQUENCY: f32 = 1.5;
+
+// These need to match the constants in `land_craft/movement.comp` and `vehicle_grid.glsl`.
+const LOOK_AHEAD_DISTANCE: f32 = 0.1;
+const ROUTE_PROGRESS_STEP: f32 = 0.02;
+const MAX_ROUTE_PROGRESS_STEPS: u32 = 32;
+const MAX_TURN_RATE: f32 = 3.0;
+const AVOIDANCE_WEIGHT: f32 = 2.0;
+const AVOIDANCE_VEER: f32 = 0.5;
+const MIN_AVOIDANCE_SPEED: f32 = 0.2;
+const CRAFT_AVOIDANCE_RADII: f32 = 4.0;
+
+/// The ground that the vehicles move over, including any sand that has moved
+/// and any tracks pressed into it.
+pub trait Ground {
+    fn height(&self, position: Vec2) -> f32;
+    fn normal(&self, position: Vec2) -> Vec3;
+}
+
+/// The baked terrain, without any sand movement or tracks.
+impl Ground for HeightField {
+    fn height(&self, position: Vec2) -> f32 {
+        self.height_at(position.x, position.y)
+    }
+
+    fn normal(&self, position: Vec2) -> Vec3 {
+        self.normal_at(position.x, position.y)
+    }
+}
+
+/// The same as `particles/movement.comp`.
+pub fn move_particle(mut particle: Particle, particle_duration: f32, time: Time) -> Particle {
+    particle.position += particle.velocity * time.delta_time;
+    // `time_spawned` is used as the percentage of the particle's life so far.
+    particle.time_spawned = (particle.time_spawned + time.delta_time / particle_duration).min(1.0);
+    particle
+}
+
+/// The same as `ship/movement.comp`, without spawning the exhaust particles.
+/// `ships` are the ships at the start of the step, which the flocking looks at.
+pub fn move_ship(
+    ships: &[Ship],
+    index: usize,
+    bob_amplitude: f32,
+    settings: &Settings,
+    time: Time,
+    scene_bounds: SceneBounds,
+    ground: &impl Ground,
+) -> Ship {
+    let mut ship = ships[index];
+
+    let previous_facing = ship.y_rotation;
+
+    if settings.flocking == 0 {
+        ship.y_rotation += ship.rotation_speed * time.delta_time;
+    } else {
+        let turn = shortest_turn(flocking_facing(ships, index, settings) - ship.y_rotation);
+
+        let max_turn = MAX_FLOCKING_TURN_RATE * time.delta_time;
+        ship.y_rotation += clamp(turn, -max_turn, max_turn)
+            + ship.rotation_speed * FLOCKING_WANDER * time.delta_time;
+    }
+
+    let forwards_xz = heading(ship.y_rotation);
+    let movement = forwards_xz * (SHIP_SPEED * time.delta_time);
+
+    let scene_center = scene_bounds.center();
+    let position = scene_center
+        + repeat_over_bounds(
+            xz(ship.position) + movement - scene_center,
+            settings.ship_movement_bounds,
+        );
+
+    let look_ahead = forwards_xz * (SHIP_SPEED * ALTITUDE_LOOK_AHEAD);
+    let ground_height = ground
+        .height(position)
+        .max(ground.height(position + look_ahead));
+
+    let bob = ((time.time_since_start * BOB_FREQUENCY + index as f32 * 0.618) * 2.0 * PI).sin()
+        * bob_amplitude;
+
+    ship.position = Vec3::new(
+        position.x,
+        approach(
+            ship.position.y,
+            ground_height + settings.ship_clearance + bob,
+            ALTITUDE_RESPONSE,
+            time,
+        ),
+        position.y,
+    );
+
+    let turn_rate = (ship.y_rotation - previous_facing) / time.delta_time;
+    let target_roll = clamp(turn_rate * BANK_PER_TURN_RATE, -MAX_BANK, MAX_BANK);
+    ship.roll = approach(ship.roll, target_roll, BANK_RESPONSE, time);
+
+    let forwards = Vec3::new(forwards_xz.x, 0.0, forwards_xz.y);
+    let side = Vec3::new(-forwards_xz.y, 0.0, forwards_xz.x);
+    let up = Vec3::new(0.0, ship.roll.cos(), 0.0) + side * ship.roll.sin();
+
+    ship._rotation_matrix = rotation_matrix(forwards, up);
+
+    ship
+}
+
+/// Unlike the shader, this looks through all of the other ships instead of
+/// just those in the neighbouring grid cells, which finds the same neighbours.
+fn flocking_facing(ships: &[Ship], index: usize, settings: &Settings) -> f32 {
+    let ship = ships[index];
+    let position = xz(ship.position);
+    let radius = settings
+        .boid_neighbour_radius
+        .min(settings.max_boid_neighbour_radius());
+
+    let mut separation = Vec2::zero();
+    let mut alignment = Vec2::zero();
+    let mut cohesion = Vec2::zero();
+    let mut num_neighbours = 0;
+
+    for (i, other) in ships.iter().enumerate() {
+        if i == index {
+            continue;
+        }
+
+        let offset =
+            repeat_over_bounds(xz(other.position) - position, settings.ship_movement_bounds);
+        let distance = offset.mag();
+
+        if distance >= radius || distance == 0.0 {
+            continue;
+        }
+
+        separation -= (offset / distance) * (1.0 - distance / radius);
+        alignment += heading(other.y_rotation);
+        cohesion += offset;
+        num_neighbours += 1;
+    }
+
+    if num_neighbours == 0 {
+        return ship.y_rotation;
+    }
+
+    alignment /= num_neighbours as f32;
+    cohesion /= num_neighbours as f32 * radius;
+
+    let desired = heading(ship.y_rotation)
+        + separation * settings.boid_separation_weight
+        + alignment * settings.boid_alignment_weight
+        + cohesion * settings.boid_cohesion_weight;
+
+    desired.y.atan2(desired.x)
+}
+
+/// The same as `land_craft/movement.comp`, without spawning the smoke and sand
+/// particles or counting collisions. `crafts` are the crafts at the start of
+/// the step, which the avoidance looks at.
+pub fn move_land_craft(
+    crafts: &[LandCraft],
+    index: usize,
+    settings: &Settings,
+    time: Time,
+    routes: &Routes,
+    ground: &impl Ground,
+) -> LandCraft {
+    let mut craft = crafts[index];
+
+    let route = craft.route as usize;
+    let num_waypoints = routes.num_waypoints(route) as f32;
+    let mut target = routes.point_at(route, craft.route_progress);
+
+    for _ in 0..MAX_ROUTE_PROGRESS_STEPS {
+        if (target - xz(craft.position)).mag() >= LOOK_AHEAD_DISTANCE {
+            break;
+        }
+
+        craft.route_progress = glsl_mod(craft.route_progress + ROUTE_PROGRESS_STEP, num_waypoints);
+        target = routes.point_at(route, craft.route_progress);
+    }
+
+    let to_target = target - xz(craft.position);
+    // Keep going the same way if the craft is right on top of the target.
+    let mut desired_direction = if to_target.mag() > 0.0 {
+        to_target.normalized()
+    } else {
+        heading(craft.facing)
+    };
+    let mut speed = craft.speed;
+
+    if settings.land_craft_avoidance != 0 {
+        let (steering, speed_scale) = avoid_crafts(crafts, index, settings);
+        desired_direction += steering * AVOIDANCE_WEIGHT;
+        speed *= speed_scale;
+    }
+
+    let turn = shortest_turn(desired_direction.y.atan2(desired_direction.x) - craft.facing);
+    let max_turn = MAX_TURN_RATE * time.delta_time;
+    craft.facing = glsl_mod(craft.facing + clamp(turn, -max_turn, max_turn), 2.0 * PI);
+
+    let position = xz(craft.position) + heading(craft.facing) * (speed * time.delta_time);
+
+    craft.position = Vec3::new(position.x, ground.height(position), position.y);
+
+    let up = ground.normal(position);
+    let facing = Vec3::new(craft.facing.cos(), 0.0, craft.facing.sin());
+    let forwards = (facing - up * facing.dot(up)).normalized();
+
+    craft._rotation_matrix = rotation_matrix(forwards, up);
+
+    craft
+}
+
+/// The steering away from nearby crafts and how much to slow down for those in
+/// front. Like `flocking_facing`, this looks through all of the other crafts.
+fn avoid_crafts(crafts: &[LandCraft], index: usize, settings: &Settings) -> (Vec2, f32) {
+    let craft = crafts[index];
+    let position = xz(craft.position);
+    let heading = heading(craft.facing);
+
+    let avoidance_distance = settings.land_craft_radius * CRAFT_AVOIDANCE_RADII;
+    let overlap_distance = settings.land_craft_radius * 2.0;
+    let right = Vec2::new(-heading.y, heading.x);
+
+    let mut steering = Vec2::zero();
+    let mut speed_scale: f32 = 1.0;
+
+    for (i, other) in crafts.iter().enumerate() {
+        if i == index {
+            continue;
+        }
+
+        let offset = position - xz(other.position);
+        let distance = offset.mag();
+
+        if distance >= avoidance_distance {
+            continue;
+        }
+
+        let away = if distance > 0.0 {
+            offset / distance
+        } else {
+            right
+        };
+        let closeness = 1.0 - distance / avoidance_distance;
+        let in_front = away.dot(heading) < -0.5;
+
+        let veer = if in_front {
+            right * AVOIDANCE_VEER
+        } else {
+            Vec2::zero()
+        };
+
+        steering += (away + veer) * closeness;
+
+        if in_front {
+            let gap =
+                (distance - overlap_distance).max(0.0) / (avoidance_distance - overlap_distance);
+            speed_scale = speed_scale.min(gap.max(MIN_AVOIDANCE_SPEED));
+        }
+    }
+
+    (steering, speed_scale)
+}
+
+fn xz(position: Vec3) -> Vec2 {
+    Vec2::new(position.x, position.z)
+}
+
+fn heading(facing: f32) -> Vec2 {
+    Vec2::new(facing.cos(), facing.sin())
+}
+
+fn clamp(value: f32, min: f32, max: f32) -> f32 {
+    value.max(min).min(max)
+}
+
+/// GLSL's `mod`, which unlike `%` takes the sign of `y`.
+fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
+
+/// Wrap an angle to between -PI and PI, to turn by the smaller amount.
+fn shortest_turn(turn: f32) -> f32 {
+    glsl_mod(turn + PI, 2.0 * PI) - PI
+}
+
+/// Move smoothly towards a target, as `approach` does in `ship/movement.comp`.
+fn approach(current: f32, target: f32, response: f32, time: Time) -> f32 {
+    current + (target - current) * (1.0 - (-response * time.delta_time).exp())
+}
+
+/// The same as `repeat_over_bounds` in `utils.glsl`.
+fn repeat_over_bounds(position: Vec2, bounds: f32) -> Vec2 {
+    let repeat = |value: f32| {
+        if value.abs() > bounds {
+            value - value.signum() * bounds * 2.0
+        } else {
+            value
+        }
+    };
+
+    Vec2::new(repeat(position.x), repeat(position.y))
+}
+
+/// The columns of `mat3(forwards, up, cross(forwards, up))`, padded out in the
+/// same way as in the storage buffers.
+fn rotation_matrix(forwards: Vec3, up: Vec3) -> [Vec4; 3] {
+    let column = |vector: Vec3| Vec4::new(vector.x, vector.y, vector.z, 0.0);
+    [column(forwards), column(up), column(forwards.cross(up))]
+}
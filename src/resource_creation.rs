@@ -1,53 +1,245 @@
+use crate::height_field::SAMPLE_OFFSETS as HEIGHT_FIELD_SAMPLE_OFFSETS;
 use crate::resources_and_pipelines::{Pipelines, RenderResources};
-use crate::{FRAMEBUFFER_FORMAT, INDEX_FORMAT};
+use crate::routes::Routes;
+use crate::{
+    DEPTH_FORMAT, FRAMEBUFFER_FORMAT, HEIGHT_MAP_NORMALS_FORMAT, HEIGHT_MAP_TANGENTS_FORMAT,
+    HEIGHT_MAP_UVS_FORMAT, INDEX_FORMAT, SHADOW_MAP_DEBUG_FORMAT,
+};
 use rand::Rng;
-use ultraviolet::Vec3;
+use std::ops::RangeInclusive;
+use ultraviolet::{Vec2, Vec3};
 use wgpu::util::DeviceExt;
 
+/// The terrain baked from above over the scene bounds.
+pub struct HeightMap {
+    pub texture: wgpu::TextureView,
+    pub normals: wgpu::TextureView,
+    /// The texture coordinates and tangents of the terrain mesh, so that terrain
+    /// drawn from the height map can still use the scene's textures.
+    pub uvs: wgpu::TextureView,
+    pub tangents: wgpu::TextureView,
+}
+
 pub fn create_height_map(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     pipelines: &Pipelines,
+    bind_group: &wgpu::BindGroup,
     vertices: &wgpu::Buffer,
     indices: &wgpu::Buffer,
     num_indices: u32,
-) -> wgpu::TextureView {
+    scene_bounds: primitives::SceneBounds,
+    size: u32,
+    format: wgpu::TextureFormat,
+) -> HeightMap {
     let height_map_texture = create_texture(
         &device,
         "height map texture",
-        1024,
-        1024,
-        wgpu::TextureFormat::R32Float,
+        size,
+        size,
+        format,
+        wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    );
+
+    let normal_map_texture = create_texture(
+        &device,
+        "height map normals texture",
+        size,
+        size,
+        HEIGHT_MAP_NORMALS_FORMAT,
+        wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    );
+
+    let uv_map_texture = create_texture(
+        &device,
+        "height map uvs texture",
+        size,
+        size,
+        HEIGHT_MAP_UVS_FORMAT,
+        wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    );
+
+    let tangent_map_texture = create_texture(
+        &device,
+        "height map tangents texture",
+        size,
+        size,
+        HEIGHT_MAP_TANGENTS_FORMAT,
         wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
     );
 
+    let depth_texture = create_texture(
+        &device,
+        "height map depth texture",
+        size,
+        size,
+        DEPTH_FORMAT,
+        wgpu::TextureUsage::RENDER_ATTACHMENT,
+    );
+
+    // Draw the terrain once for each sample of a texel, so that the height map
+    // keeps the highest height over the whole of each texel like `HeightField`
+    // does. Moving the terrain moves where the texel centers land on it.
+    let sample_offsets: Vec<Vec2> = HEIGHT_FIELD_SAMPLE_OFFSETS
+        .iter()
+        .flat_map(|&y| {
+            HEIGHT_FIELD_SAMPLE_OFFSETS
+                .iter()
+                .map(move |&x| Vec2::new(x, y) * 2.0 / size as f32)
+        })
+        .collect();
+
+    let sample_offsets_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("height map sample offsets buffer"),
+        usage: wgpu::BufferUsage::VERTEX,
+        contents: bytemuck::cast_slice(&sample_offsets),
+    });
+
     let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
         label: Some("height map encoder"),
     });
 
     let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("height map render pass"),
-        color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-            attachment: &height_map_texture,
-            resolve_target: None,
-            ops: wgpu::Operations {
-                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                store: true,
-            },
-        }],
-        depth_stencil_attachment: None,
+        color_attachments: &[
+            wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &height_map_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // The lowest height of the terrain wherever it doesn't cover.
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: scene_bounds.min_height as f64,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 0.0,
+                    }),
+                    store: true,
+                },
+            },
+            wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &normal_map_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Flat ground wherever the terrain doesn't cover.
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.0,
+                        g: 1.0,
+                        b: 0.0,
+                        a: 0.0,
+                    }),
+                    store: true,
+                },
+            },
+            wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &uv_map_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            },
+            wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &tangent_map_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Along +x wherever the terrain doesn't cover.
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 1.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            },
+        ],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+            attachment: &depth_texture,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: false,
+            }),
+            stencil_ops: None,
+        }),
     });
 
-    render_pass.set_pipeline(&pipelines.bake_height_map_pipeline);
+    render_pass.set_pipeline(pipelines.bake_height_map_pipeline(format));
+    render_pass.set_bind_group(0, bind_group, &[]);
     render_pass.set_vertex_buffer(0, vertices.slice(..));
+    render_pass.set_vertex_buffer(1, sample_offsets_buffer.slice(..));
     render_pass.set_index_buffer(indices.slice(..), INDEX_FORMAT);
-    render_pass.draw_indexed(0..num_indices, 0, 0..1);
+    render_pass.draw_indexed(0..num_indices, 0, 0..sample_offsets.len() as u32);
 
     drop(render_pass);
 
     queue.submit(Some(encoder.finish()));
 
-    height_map_texture
+    HeightMap {
+        texture: height_map_texture,
+        normals: normal_map_texture,
+        uvs: uv_map_texture,
+        tangents: tangent_map_texture,
+    }
+}
+
+pub fn create_main_bind_group(
+    device: &wgpu::Device,
+    resources: &RenderResources,
+    camera_buffer: &wgpu::Buffer,
+    sun_buffer: &wgpu::Buffer,
+    settings_buffer: &wgpu::Buffer,
+    time_buffer: &wgpu::Buffer,
+    scene_bounds_buffer: &wgpu::Buffer,
+    sand_tracks: &wgpu::TextureView,
+    sand_offset: &wgpu::TextureView,
+    terrain_material_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("bind group"),
+        layout: &resources.main_bgl,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: sun_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&resources.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: settings_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: time_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(&resources.clamp_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: scene_bounds_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(sand_tracks),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(sand_offset),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: terrain_material_buffer.as_entire_binding(),
+            },
+        ],
+    })
 }
 
 pub fn create_texture(
@@ -113,32 +305,193 @@ pub fn framebuffer_and_tonemapper_bind_group(
     (framebuffer_texture, tonemapper_bind_group)
 }
 
-fn create_particle_bind_group(
+/// Create the textures that the shadow maps are drawn into for display in the
+/// UI, and register them with egui.
+pub fn create_shadow_map_debug_textures(
+    device: &wgpu::Device,
+    egui_renderpass: &mut egui_wgpu_backend::RenderPass,
+    size: u32,
+) -> ([wgpu::TextureView; 3], [egui::TextureId; 3]) {
+    let mut texture = |label| {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_MAP_DEBUG_FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+
+        let texture_id = egui_renderpass.egui_texture_from_wgpu_texture(device, &texture);
+
+        (
+            texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture_id,
+        )
+    };
+
+    let (near_view, near_id) = texture("near shadow map debug texture");
+    let (middle_view, middle_id) = texture("middle shadow map debug texture");
+    let (far_view, far_id) = texture("far shadow map debug texture");
+
+    (
+        [near_view, middle_view, far_view],
+        [near_id, middle_id, far_id],
+    )
+}
+
+pub fn create_shadow_map_debug_bind_groups(
+    device: &wgpu::Device,
+    resources: &RenderResources,
+    shadow_textures: &[wgpu::TextureView; 3],
+    shadow_debug_buffer: &wgpu::Buffer,
+) -> [wgpu::BindGroup; 3] {
+    let bind_group = |label, texture| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout: &resources.shadow_map_debug_bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&resources.clamp_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: shadow_debug_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    };
+
+    [
+        bind_group("near shadow map debug bind group", &shadow_textures[0]),
+        bind_group("middle shadow map debug bind group", &shadow_textures[1]),
+        bind_group("far shadow map debug bind group", &shadow_textures[2]),
+    ]
+}
+
+/// The storage buffer behind a set of vehicles. When the number of vehicles
+/// changes, this is copied into the new buffer so that the existing vehicles
+/// carry on from where they were.
+pub struct VehicleBuffers {
+    vehicles: wgpu::Buffer,
+    num_vehicles: u32,
+    /// Only the land craft follow routes.
+    routes: Option<RouteBuffers>,
+}
+
+struct RouteBuffers {
+    waypoints: wgpu::Buffer,
+    routes: wgpu::Buffer,
+}
+
+impl VehicleBuffers {
+    pub fn vehicles(&self) -> &wgpu::Buffer {
+        &self.vehicles
+    }
+
+    pub fn num_vehicles(&self) -> u32 {
+        self.num_vehicles
+    }
+
+    /// The number of vehicles that can be carried over into `num_vehicles` new ones.
+    fn num_kept(previous: Option<&Self>, num_vehicles: u32) -> u32 {
+        previous.map_or(0, |previous| previous.num_vehicles.min(num_vehicles))
+    }
+
+    fn copy_into<T>(&self, new: &Self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("vehicle buffers copy encoder"),
+        });
+
+        let num_vehicles = self.num_vehicles.min(new.num_vehicles) as u64;
+
+        encoder.copy_buffer_to_buffer(
+            &self.vehicles,
+            0,
+            &new.vehicles,
+            0,
+            num_vehicles * std::mem::size_of::<T>() as u64,
+        );
+
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// The storage buffers behind a set of particles, which are copied over in the
+/// same way as `VehicleBuffers`.
+pub struct ParticleBuffers {
+    pub particles: wgpu::Buffer,
+    pub info: wgpu::Buffer,
+    num: u64,
+}
+
+impl ParticleBuffers {
+    pub fn copy_into(&self, new: &Self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("particle buffers copy encoder"),
+        });
+
+        let num_particles = self.num.min(new.num);
+
+        encoder.copy_buffer_to_buffer(
+            &self.particles,
+            0,
+            &new.particles,
+            0,
+            num_particles * std::mem::size_of::<primitives::Particle>() as u64,
+        );
+
+        // This keeps the spawn offset and timing going, so the particles don't
+        // all restart at once.
+        encoder.copy_buffer_to_buffer(
+            &self.info,
+            0,
+            &new.info,
+            0,
+            std::mem::size_of::<primitives::ParticlesBufferInfo>() as u64,
+        );
+
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// `info` is the initial info of the particles, which is mostly where and how
+/// they're spawned.
+pub fn create_particle_bind_group(
     device: &wgpu::Device,
     name: &str,
     num: u64,
-    colour: Vec3,
-    half_size_linear: f32,
+    info: primitives::ParticlesBufferInfo,
     resources: &RenderResources,
-) -> wgpu::BindGroup {
+) -> (wgpu::BindGroup, ParticleBuffers) {
     let particles_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some(&format!("{} buffer", name)),
-        usage: wgpu::BufferUsage::STORAGE,
+        usage: wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::COPY_SRC
+            | wgpu::BufferUsage::COPY_DST,
         size: std::mem::size_of::<primitives::Particle>() as u64 * num,
         mapped_at_creation: false,
     });
 
     let particles_buffer_info = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some(&format!("{} info buffer", name)),
-        usage: wgpu::BufferUsage::STORAGE,
-        contents: bytemuck::bytes_of(&primitives::ParticlesBufferInfo {
-            colour,
-            half_size_linear,
-            ..Default::default()
-        }),
+        usage: wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::COPY_SRC
+            | wgpu::BufferUsage::COPY_DST,
+        contents: bytemuck::bytes_of(&info),
     });
 
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some(&format!("{} bind group", name)),
         layout: &resources.particles_bgl,
         entries: &[
@@ -151,125 +504,335 @@ fn create_particle_bind_group(
                 resource: particles_buffer_info.as_entire_binding(),
             },
         ],
+    });
+
+    (
+        bind_group,
+        ParticleBuffers {
+            particles: particles_buffer,
+            info: particles_buffer_info,
+            num,
+        },
+    )
+}
+
+/// The maximum number of cells along each side of the grid that vehicles are
+/// sorted into to find their neighbours. This needs to match `MAX_GRID_SIZE` in
+/// `vehicle_grid.glsl`.
+pub const MAX_GRID_SIZE: u32 = 64;
+
+/// Create the grid that `num_vehicles` vehicles of one type are sorted into, for
+/// flocking or for avoiding each other. Flying vehicles wrap around, so their
+/// grid does too.
+pub fn create_vehicle_grid(
+    device: &wgpu::Device,
+    resources: &RenderResources,
+    name: &str,
+    num_vehicles: u32,
+    wraps: bool,
+) -> wgpu::BindGroup {
+    let num_cells = MAX_GRID_SIZE * MAX_GRID_SIZE;
+
+    let grid_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} grid buffer", name)),
+        usage: wgpu::BufferUsage::UNIFORM,
+        contents: bytemuck::bytes_of(&primitives::VehicleGrid {
+            wraps: wraps as u32,
+            ..Default::default()
+        }),
+    });
+
+    // The count and start of each cell.
+    let cells_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{} grid cells buffer", name)),
+        size: (num_cells * 8) as u64,
+        usage: wgpu::BufferUsage::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let vehicle_cells_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{} cells buffer", name)),
+        size: (num_vehicles * 8) as u64,
+        usage: wgpu::BufferUsage::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let sorted_vehicles_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("sorted {} buffer", name)),
+        size: (num_vehicles * 16) as u64,
+        usage: wgpu::BufferUsage::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{} grid bind group", name)),
+        layout: &resources.vehicle_grid_bgl,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: grid_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: cells_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: vehicle_cells_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: sorted_vehicles_buffer.as_entire_binding(),
+            },
+        ],
     })
 }
 
+/// How many random spots are tried for each vehicle before settling for one
+/// that doesn't follow its placement rules.
+const MAX_PLACEMENT_ATTEMPTS: u32 = 16;
+
+/// Pick spots with `random_spot` until `can_place` allows one's position on
+/// the xz plane, or keep the last one tried after `MAX_PLACEMENT_ATTEMPTS`.
+fn place<T>(
+    rng: &mut rand::rngs::StdRng,
+    can_place: &impl Fn(Vec2) -> bool,
+    random_spot: impl Fn(&mut rand::rngs::StdRng) -> (Vec2, T),
+) -> (Vec2, T) {
+    let mut spot = random_spot(rng);
+
+    for _ in 1..MAX_PLACEMENT_ATTEMPTS {
+        if can_place(spot.0) {
+            break;
+        }
+
+        spot = random_spot(rng);
+    }
+
+    spot
+}
+
+/// Create the buffers for `num_ships` ships, which turn at a random speed in
+/// `turn_speeds` when they aren't flocking and are spawned where `can_place`
+/// allows. If `previous` is given, the ships in it are kept and only the extra
+/// ships are spawned.
 pub fn create_ships(
     num_ships: u32,
+    previous: Option<&VehicleBuffers>,
     device: &wgpu::Device,
-    rng: &mut rand::rngs::ThreadRng,
-    resources: &RenderResources,
-) -> (wgpu::BindGroup, u32, wgpu::BindGroup) {
+    queue: &wgpu::Queue,
+    rng: &mut rand::rngs::StdRng,
+    settings: &primitives::Settings,
+    scene_bounds: primitives::SceneBounds,
+    turn_speeds: RangeInclusive<f32>,
+    can_place: impl Fn(Vec2) -> bool,
+) -> VehicleBuffers {
+    let num_kept = VehicleBuffers::num_kept(previous, num_ships);
+
     let ship_positions: Vec<_> = (0..num_ships)
-        .map(|_| primitives::Ship {
-            position: Vec3::new(
-                rng.gen_range(-2.0..=2.0),
-                rng.gen_range(0.49..=0.51),
-                rng.gen_range(-2.0..=2.0),
-            ),
-            y_rotation: rng.gen_range(0.0..360.0_f32.to_radians()),
-            rotation_speed: rng.gen_range(-0.02..=0.02),
-            ..Default::default()
+        .map(|i| {
+            if i < num_kept {
+                // Copied over from the previous buffer.
+                return primitives::Ship::default();
+            }
+
+            let (position, ()) = place(rng, &can_place, |rng| {
+                let position = Vec2::new(
+                    rng.gen_range(scene_bounds.min.x..=scene_bounds.max.x),
+                    rng.gen_range(scene_bounds.min.y..=scene_bounds.max.y),
+                );
+                (position, ())
+            });
+
+            primitives::Ship {
+                position: Vec3::new(
+                    position.x,
+                    // The ships settle down to their clearance above the ground.
+                    scene_bounds.max_height + settings.ship_clearance,
+                    position.y,
+                ),
+                y_rotation: rng.gen_range(0.0..360.0_f32.to_radians()),
+                rotation_speed: rng.gen_range(turn_speeds.clone()),
+                ..Default::default()
+            }
         })
         .collect();
 
     let ship_positions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("ship positions buffer"),
-        usage: wgpu::BufferUsage::STORAGE,
+        usage: wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::COPY_SRC
+            | wgpu::BufferUsage::COPY_DST,
         contents: bytemuck::cast_slice(&ship_positions),
     });
 
-    let ship_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("ship bind group"),
-        layout: &resources.ship_bgl,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: ship_positions_buffer.as_entire_binding(),
-        }],
-    });
+    let buffers = VehicleBuffers {
+        vehicles: ship_positions_buffer,
+        num_vehicles: num_ships,
+        routes: None,
+    };
 
-    let particles_per_ship = 15 * 2;
-    let num_exhaust_particles = num_ships * particles_per_ship;
-    let exhaust_particles_bind_group = create_particle_bind_group(
-        &device,
-        "exhaust particles",
-        num_exhaust_particles as u64,
-        Vec3::new(0.5, 0.75, 1.0),
-        0.02,
-        &resources,
-    );
+    if let Some(previous) = previous {
+        previous.copy_into::<primitives::Ship>(&buffers, device, queue);
+    }
 
-    (
-        ship_bind_group,
-        particles_per_ship,
-        exhaust_particles_bind_group,
-    )
+    buffers
 }
 
-pub fn create_land_craft(
-    num_land_craft: u32,
+/// Bind the ships to the height map that they fly over. This is created again
+/// whenever the height map is baked, without touching the ships themselves.
+pub fn create_ship_bind_group(
     device: &wgpu::Device,
-    rng: &mut rand::rngs::ThreadRng,
     resources: &RenderResources,
+    buffers: &VehicleBuffers,
     height_map_texture: &wgpu::TextureView,
-    settings: &primitives::Settings,
-) -> (wgpu::BindGroup, u32, wgpu::BindGroup, u32, wgpu::BindGroup) {
+    type_info_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("ship bind group"),
+        layout: &resources.ship_bgl,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffers.vehicles.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(height_map_texture),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: type_info_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// Create the buffers for `num_land_craft` land craft, which drive at a random
+/// speed in `speeds` and are spawned along the routes where `can_place`
+/// allows. If `previous` is given, the crafts in it are kept and only
+/// the extra crafts are spawned.
+pub fn create_land_craft(
+    num_land_craft: u32,
+    previous: Option<&VehicleBuffers>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    rng: &mut rand::rngs::StdRng,
+    routes: &Routes,
+    speeds: RangeInclusive<f32>,
+    can_place: impl Fn(Vec2) -> bool,
+) -> VehicleBuffers {
+    let num_kept = VehicleBuffers::num_kept(previous, num_land_craft);
+
     let land_craft: Vec<_> = (0..num_land_craft)
-        .map(|_| primitives::LandCraft {
-            position: Vec3::new(rng.gen_range(-2.0..=2.0), 0.0, rng.gen_range(-2.0..=2.0)),
-            facing: rng.gen_range(0.0..360.0_f32.to_radians()),
-            ..Default::default()
+        .map(|i| {
+            if i < num_kept {
+                // Copied over from the previous buffer.
+                return primitives::LandCraft::default();
+            }
+
+            let (position, (route, route_progress)) = place(rng, &can_place, |rng| {
+                let route = rng.gen_range(0..routes.len());
+                let route_progress = rng.gen_range(0.0..routes.num_waypoints(route) as f32);
+                (
+                    routes.point_at(route, route_progress),
+                    (route, route_progress),
+                )
+            });
+
+            primitives::LandCraft {
+                position: Vec3::new(position.x, 0.0, position.y),
+                facing: routes.facing_at(route, route_progress),
+                route: route as u32,
+                route_progress,
+                speed: rng.gen_range(speeds.clone()),
+                ..Default::default()
+            }
         })
         .collect();
 
     let land_craft_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("land craft buffer"),
-        usage: wgpu::BufferUsage::STORAGE,
+        usage: wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::COPY_SRC
+            | wgpu::BufferUsage::COPY_DST,
         contents: bytemuck::cast_slice(&land_craft),
     });
 
-    let land_craft_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    let (waypoints, route_table) = routes.flatten();
+
+    let waypoints_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("route waypoints buffer"),
+        usage: wgpu::BufferUsage::STORAGE,
+        contents: bytemuck::cast_slice(&waypoints),
+    });
+
+    let routes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("routes buffer"),
+        usage: wgpu::BufferUsage::STORAGE,
+        contents: bytemuck::cast_slice(&route_table),
+    });
+
+    let buffers = VehicleBuffers {
+        vehicles: land_craft_buffer,
+        num_vehicles: num_land_craft,
+        routes: Some(RouteBuffers {
+            waypoints: waypoints_buffer,
+            routes: routes_buffer,
+        }),
+    };
+
+    if let Some(previous) = previous {
+        previous.copy_into::<primitives::LandCraft>(&buffers, device, queue);
+    }
+
+    buffers
+}
+
+/// Bind the land craft to the height map that they drive over, along with
+/// their routes. Like `create_ship_bind_group`, this is created again whenever
+/// the height map is baked.
+pub fn create_land_craft_bind_group(
+    device: &wgpu::Device,
+    resources: &RenderResources,
+    buffers: &VehicleBuffers,
+    height_map_texture: &wgpu::TextureView,
+    height_map_normals_texture: &wgpu::TextureView,
+    collisions_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    let routes = buffers
+        .routes
+        .as_ref()
+        .expect("Land craft buffers are always created with routes");
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("land craft bind group"),
         layout: &resources.land_craft_bgl,
         entries: &[
             wgpu::BindGroupEntry {
                 binding: 0,
-                resource: land_craft_buffer.as_entire_binding(),
+                resource: buffers.vehicles.as_entire_binding(),
             },
             wgpu::BindGroupEntry {
                 binding: 1,
                 resource: wgpu::BindingResource::TextureView(height_map_texture),
             },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(height_map_normals_texture),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: routes.waypoints.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: routes.routes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: collisions_buffer.as_entire_binding(),
+            },
         ],
-    });
-
-    let smoke_particles_per_landcraft = 45;
-    let num_smoke_particles = num_land_craft * smoke_particles_per_landcraft;
-    let smoke_particles_bind_group = create_particle_bind_group(
-        &device,
-        "smoke particles",
-        num_smoke_particles as u64,
-        Vec3::broadcast(0.15),
-        0.03,
-        &resources,
-    );
-
-    let sand_particles_per_landcraft = 10;
-    let num_sand_particles = num_land_craft * sand_particles_per_landcraft;
-    let sand_particles_bind_group = create_particle_bind_group(
-        &device,
-        "sand particles",
-        num_sand_particles as u64,
-        settings.base_colour * 0.4,
-        0.1,
-        &resources,
-    );
-
-    (
-        land_craft_bind_group,
-        num_smoke_particles,
-        smoke_particles_bind_group,
-        num_sand_particles,
-        sand_particles_bind_group,
-    )
+    })
 }
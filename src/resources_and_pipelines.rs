@@ -1,16 +1,26 @@
-use crate::{DEPTH_FORMAT, FRAMEBUFFER_FORMAT};
+use crate::{
+    DEPTH_FORMAT, FRAMEBUFFER_FORMAT, HEIGHT_MAP_FORMATS, HEIGHT_MAP_NORMALS_FORMAT,
+    HEIGHT_MAP_TANGENTS_FORMAT, HEIGHT_MAP_UVS_FORMAT, SHADOW_MAP_DEBUG_FORMAT,
+};
 use cascaded_shadow_maps::CascadedShadowMaps;
-use primitives::Vertex;
+use primitives::{TerrainChunk, Vertex};
+use ultraviolet::Vec2;
 
 /// All the permament resources that we can load before creating a window.
 pub struct RenderResources {
     pub main_bgl: wgpu::BindGroupLayout,
     pub single_texture_bgl: wgpu::BindGroupLayout,
-    pub double_texture_bgl: wgpu::BindGroupLayout,
     pub tonemap_bgl: wgpu::BindGroupLayout,
     pub ship_bgl: wgpu::BindGroupLayout,
+    pub vehicle_bgl: wgpu::BindGroupLayout,
     pub particles_bgl: wgpu::BindGroupLayout,
     pub land_craft_bgl: wgpu::BindGroupLayout,
+    pub vehicle_grid_bgl: wgpu::BindGroupLayout,
+    pub shadow_map_debug_bgl: wgpu::BindGroupLayout,
+    pub sand_tracks_bgl: wgpu::BindGroupLayout,
+    pub sand_simulation_bgl: wgpu::BindGroupLayout,
+    pub terrain_chunks_bgl: wgpu::BindGroupLayout,
+    pub terrain_textures_bgl: wgpu::BindGroupLayout,
     pub sampler: wgpu::Sampler,
     pub clamp_sampler: wgpu::Sampler,
 }
@@ -69,20 +79,17 @@ impl RenderResources {
                     sampler(2, wgpu::ShaderStage::FRAGMENT),
                     uniform(3, wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE),
                     uniform(4, wgpu::ShaderStage::all()),
-                    sampler(5, wgpu::ShaderStage::COMPUTE),
+                    sampler(5, wgpu::ShaderStage::all()),
+                    uniform(6, wgpu::ShaderStage::all()),
+                    texture(7, wgpu::ShaderStage::all()),
+                    texture(8, wgpu::ShaderStage::all()),
+                    uniform(9, wgpu::ShaderStage::FRAGMENT),
                 ],
             }),
             single_texture_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("single texxture bind group layout"),
                 entries: &[texture(0, wgpu::ShaderStage::FRAGMENT)],
             }),
-            double_texture_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("double texture bind group layout"),
-                entries: &[
-                    texture(0, wgpu::ShaderStage::FRAGMENT),
-                    texture(1, wgpu::ShaderStage::FRAGMENT),
-                ],
-            }),
             tonemap_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("tonemapper bind group layout"),
                 entries: &[
@@ -93,11 +100,23 @@ impl RenderResources {
             }),
             ship_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("ship bind group layout"),
-                entries: &[storage(
-                    0,
-                    wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::COMPUTE,
-                    false,
-                )],
+                entries: &[
+                    storage(0, wgpu::ShaderStage::COMPUTE, false),
+                    texture(1, wgpu::ShaderStage::COMPUTE),
+                    uniform(2, wgpu::ShaderStage::COMPUTE),
+                ],
+            }),
+            // What's needed to render any type of vehicle and spawn its particles.
+            vehicle_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("vehicle bind group layout"),
+                entries: &[
+                    storage(
+                        0,
+                        wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::COMPUTE,
+                        true,
+                    ),
+                    uniform(1, wgpu::ShaderStage::FRAGMENT),
+                ],
             }),
             particles_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("particles bind group layout"),
@@ -117,14 +136,86 @@ impl RenderResources {
             land_craft_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("land craft bind group layout"),
                 entries: &[
-                    storage(
-                        0,
-                        wgpu::ShaderStage::COMPUTE | wgpu::ShaderStage::VERTEX,
-                        false,
-                    ),
+                    storage(0, wgpu::ShaderStage::COMPUTE, false),
                     texture(1, wgpu::ShaderStage::COMPUTE),
+                    texture(2, wgpu::ShaderStage::COMPUTE),
+                    storage(3, wgpu::ShaderStage::COMPUTE, true),
+                    storage(4, wgpu::ShaderStage::COMPUTE, true),
+                    storage(5, wgpu::ShaderStage::COMPUTE, false),
+                ],
+            }),
+            // The spatial hash grid that any type of vehicle is sorted into to
+            // find its neighbours.
+            vehicle_grid_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("vehicle grid bind group layout"),
+                entries: &[
+                    uniform(0, wgpu::ShaderStage::COMPUTE),
+                    storage(1, wgpu::ShaderStage::COMPUTE, false),
+                    storage(2, wgpu::ShaderStage::COMPUTE, false),
+                    storage(3, wgpu::ShaderStage::COMPUTE, false),
                 ],
             }),
+            shadow_map_debug_bgl: device.create_bind_group_layout(
+                &wgpu::BindGroupLayoutDescriptor {
+                    label: Some("shadow map debug bind group layout"),
+                    entries: &[
+                        texture(0, wgpu::ShaderStage::FRAGMENT),
+                        sampler(1, wgpu::ShaderStage::FRAGMENT),
+                        uniform(2, wgpu::ShaderStage::FRAGMENT),
+                    ],
+                },
+            ),
+            sand_tracks_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("sand tracks bind group layout"),
+                entries: &[storage(0, wgpu::ShaderStage::COMPUTE, false)],
+            }),
+            sand_simulation_bgl: device.create_bind_group_layout(
+                &wgpu::BindGroupLayoutDescriptor {
+                    label: Some("sand simulation bind group layout"),
+                    entries: &[
+                        texture(0, wgpu::ShaderStage::COMPUTE),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                                format: wgpu::TextureFormat::R32Float,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                    ],
+                },
+            ),
+            terrain_chunks_bgl: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("terrain chunks bind group layout"),
+                entries: &[
+                    texture(0, wgpu::ShaderStage::VERTEX),
+                    texture(1, wgpu::ShaderStage::VERTEX),
+                    texture(2, wgpu::ShaderStage::VERTEX),
+                    texture(3, wgpu::ShaderStage::VERTEX),
+                    texture(4, wgpu::ShaderStage::VERTEX),
+                ],
+            }),
+            terrain_textures_bgl: device.create_bind_group_layout(
+                &wgpu::BindGroupLayoutDescriptor {
+                    label: Some("terrain textures bind group layout"),
+                    entries: &[
+                        texture(0, wgpu::ShaderStage::FRAGMENT),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStage::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        texture(2, wgpu::ShaderStage::FRAGMENT),
+                    ],
+                },
+            ),
             sampler: device.create_sampler(&wgpu::SamplerDescriptor {
                 label: Some("linear sampler"),
                 mag_filter: wgpu::FilterMode::Linear,
@@ -145,18 +236,31 @@ impl RenderResources {
 
 pub struct Pipelines {
     pub scene_pipeline: wgpu::RenderPipeline,
+    pub terrain_chunks_pipeline: wgpu::RenderPipeline,
     pub sun_dir_pipeline: wgpu::RenderPipeline,
+    pub cascade_frusta_pipeline: wgpu::RenderPipeline,
+    pub shadow_map_debug_pipeline: wgpu::RenderPipeline,
     pub tonemap_pipeline: wgpu::RenderPipeline,
-    pub ship_pipeline: wgpu::RenderPipeline,
-    pub land_craft_pipeline: wgpu::RenderPipeline,
+    pub vehicle_pipeline: wgpu::RenderPipeline,
     pub particles_pipeline: wgpu::RenderPipeline,
     pub scene_shadows_pipeline: wgpu::RenderPipeline,
-    pub ship_shadows_pipeline: wgpu::RenderPipeline,
-    pub land_craft_shadows_pipeline: wgpu::RenderPipeline,
+    pub terrain_chunks_shadows_pipeline: wgpu::RenderPipeline,
+    pub vehicle_shadows_pipeline: wgpu::RenderPipeline,
     pub ship_movement_pipeline: wgpu::ComputePipeline,
+    pub vehicle_grid_clear_pipeline: wgpu::ComputePipeline,
+    pub vehicle_grid_count_pipeline: wgpu::ComputePipeline,
+    pub vehicle_grid_offsets_pipeline: wgpu::ComputePipeline,
+    pub vehicle_grid_scatter_pipeline: wgpu::ComputePipeline,
+    pub vehicle_grid_sort_pipeline: wgpu::ComputePipeline,
     pub particles_movement_pipeline: wgpu::ComputePipeline,
+    pub particles_spawn_pipeline: wgpu::ComputePipeline,
+    pub particles_advance_pipeline: wgpu::ComputePipeline,
     pub land_craft_movement_pipeline: wgpu::ComputePipeline,
-    pub bake_height_map_pipeline: wgpu::RenderPipeline,
+    pub sand_tracks_fill_pipeline: wgpu::ComputePipeline,
+    pub sand_tracks_stamp_pipeline: wgpu::ComputePipeline,
+    pub sand_saltation_pipeline: wgpu::ComputePipeline,
+    pub sand_avalanche_pipeline: wgpu::ComputePipeline,
+    bake_height_map_pipelines: [wgpu::RenderPipeline; 2],
 }
 
 impl Pipelines {
@@ -166,13 +270,6 @@ impl Pipelines {
         resources: &RenderResources,
         shadow_maps: &CascadedShadowMaps,
     ) -> Self {
-        let no_bind_groups_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("no bind groups pipeline layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
-
         let main_bind_group_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("main bind group pipeline layout"),
@@ -187,6 +284,57 @@ impl Pipelines {
                 push_constant_ranges: &[],
             });
 
+        let sand_simulation_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("sand simulation pipeline layout"),
+                bind_group_layouts: &[&resources.main_bgl, &resources.sand_simulation_bgl],
+                push_constant_ranges: &[],
+            });
+
+        let ship_compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("ship compute pipeline layout"),
+                bind_group_layouts: &[
+                    &resources.main_bgl,
+                    &resources.ship_bgl,
+                    &resources.vehicle_grid_bgl,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let land_craft_compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("land craft compute pipeline layout"),
+                bind_group_layouts: &[
+                    &resources.main_bgl,
+                    &resources.land_craft_bgl,
+                    &resources.vehicle_grid_bgl,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let particles_spawn_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("particles spawn pipeline layout"),
+                bind_group_layouts: &[
+                    &resources.main_bgl,
+                    &resources.vehicle_bgl,
+                    &resources.particles_bgl,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let vehicle_grid_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("vehicle grid pipeline layout"),
+                bind_group_layouts: &[
+                    &resources.main_bgl,
+                    &resources.vehicle_bgl,
+                    &resources.vehicle_grid_bgl,
+                ],
+                push_constant_ranges: &[],
+            });
+
         let fs_flat_colour = wgpu::include_spirv!("../shaders/compiled/flat_colour.frag.spv");
         let fs_flat_colour = device.create_shader_module(&fs_flat_colour);
 
@@ -196,6 +344,19 @@ impl Pipelines {
             attributes: &wgpu::vertex_attr_array![0 => Float3, 1 => Float3, 2 => Float2, 3 => Float4],
         };
 
+        let terrain_chunk_buffer_layouts = [
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<Vec2>() as u64,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float2],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<TerrainChunk>() as u64,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![1 => Float2, 2 => Float2, 3 => Uint, 4 => Uint4, 5 => Uint2, 6 => Uint],
+            },
+        ];
+
         let depth_write = wgpu::DepthStencilState {
             format: DEPTH_FORMAT,
             depth_write_enabled: true,
@@ -217,7 +378,7 @@ impl Pipelines {
                         label: Some("scene pipeline layout"),
                         bind_group_layouts: &[
                             &resources.main_bgl,
-                            &resources.double_texture_bgl,
+                            &resources.terrain_textures_bgl,
                             shadow_maps.rendering_bind_group_layout(),
                         ],
                         push_constant_ranges: &[],
@@ -246,34 +407,35 @@ impl Pipelines {
                     multisample: wgpu::MultisampleState::default(),
                 })
             },
-            ship_pipeline: {
-                let ship_pipeline_layout =
+            terrain_chunks_pipeline: {
+                let terrain_chunks_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("ship pipeline layout"),
+                        label: Some("terrain chunks pipeline layout"),
                         bind_group_layouts: &[
                             &resources.main_bgl,
-                            &resources.ship_bgl,
-                            &resources.single_texture_bgl,
+                            &resources.terrain_textures_bgl,
                             shadow_maps.rendering_bind_group_layout(),
+                            &resources.terrain_chunks_bgl,
                         ],
                         push_constant_ranges: &[],
                     });
 
-                let vs_ship = wgpu::include_spirv!("../shaders/compiled/ship_shader.vert.spv");
-                let vs_ship = device.create_shader_module(&vs_ship);
-                let fs_ship = wgpu::include_spirv!("../shaders/compiled/ship_shader.frag.spv");
-                let fs_ship = device.create_shader_module(&fs_ship);
+                let vs_terrain_chunks =
+                    wgpu::include_spirv!("../shaders/compiled/terrain_chunks_shader.vert.spv");
+                let vs_terrain_chunks = device.create_shader_module(&vs_terrain_chunks);
+                let fs_scene = wgpu::include_spirv!("../shaders/compiled/scene_shader.frag.spv");
+                let fs_scene = device.create_shader_module(&fs_scene);
 
                 device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("ship pipeline"),
-                    layout: Some(&ship_pipeline_layout),
+                    label: Some("terrain chunks pipeline"),
+                    layout: Some(&terrain_chunks_pipeline_layout),
                     vertex: wgpu::VertexState {
-                        module: &vs_ship,
+                        module: &vs_terrain_chunks,
                         entry_point: "main",
-                        buffers: &[vertex_buffer_layout.clone()],
+                        buffers: &terrain_chunk_buffer_layouts,
                     },
                     fragment: Some(wgpu::FragmentState {
-                        module: &fs_ship,
+                        module: &fs_scene,
                         entry_point: "main",
                         targets: &[FRAMEBUFFER_FORMAT.into()],
                     }),
@@ -282,37 +444,36 @@ impl Pipelines {
                     multisample: wgpu::MultisampleState::default(),
                 })
             },
-            land_craft_pipeline: {
-                let land_craft_pipeline_layout =
+            vehicle_pipeline: {
+                let vehicle_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("land craft pipeline layout"),
+                        label: Some("vehicle pipeline layout"),
                         bind_group_layouts: &[
                             &resources.main_bgl,
-                            &resources.land_craft_bgl,
+                            &resources.vehicle_bgl,
                             &resources.single_texture_bgl,
                             shadow_maps.rendering_bind_group_layout(),
                         ],
                         push_constant_ranges: &[],
                     });
 
-                let vs_land_craft =
-                    wgpu::include_spirv!("../shaders/compiled/land_craft_shader.vert.spv");
-                let vs_land_craft = device.create_shader_module(&vs_land_craft);
-
-                let fs_land_craft =
-                    wgpu::include_spirv!("../shaders/compiled/land_craft_shader.frag.spv");
-                let fs_land_craft = device.create_shader_module(&fs_land_craft);
+                let vs_vehicle =
+                    wgpu::include_spirv!("../shaders/compiled/vehicles_shader.vert.spv");
+                let vs_vehicle = device.create_shader_module(&vs_vehicle);
+                let fs_vehicle =
+                    wgpu::include_spirv!("../shaders/compiled/vehicles_shader.frag.spv");
+                let fs_vehicle = device.create_shader_module(&fs_vehicle);
 
                 device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("land craft pipeline"),
-                    layout: Some(&land_craft_pipeline_layout),
+                    label: Some("vehicle pipeline"),
+                    layout: Some(&vehicle_pipeline_layout),
                     vertex: wgpu::VertexState {
-                        module: &vs_land_craft,
+                        module: &vs_vehicle,
                         entry_point: "main",
                         buffers: &[vertex_buffer_layout.clone()],
                     },
                     fragment: Some(wgpu::FragmentState {
-                        module: &fs_land_craft,
+                        module: &fs_vehicle,
                         entry_point: "main",
                         targets: &[FRAMEBUFFER_FORMAT.into()],
                     }),
@@ -389,6 +550,75 @@ impl Pipelines {
                     multisample: wgpu::MultisampleState::default(),
                 })
             },
+            cascade_frusta_pipeline: {
+                let cascade_frusta_pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("cascade frusta pipeline layout"),
+                        bind_group_layouts: &[
+                            &resources.main_bgl,
+                            shadow_maps.light_projection_bind_group_layout(),
+                        ],
+                        push_constant_ranges: &[],
+                    });
+
+                let vs_cascade_frusta =
+                    wgpu::include_spirv!("../shaders/compiled/cascade_frusta.vert.spv");
+                let vs_cascade_frusta = device.create_shader_module(&vs_cascade_frusta);
+
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("cascade frusta pipeline"),
+                    layout: Some(&cascade_frusta_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &vs_cascade_frusta,
+                        entry_point: "main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &fs_flat_colour,
+                        entry_point: "main",
+                        targets: &[display_format.into()],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::LineList,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(depth_write.clone()),
+                    multisample: wgpu::MultisampleState::default(),
+                })
+            },
+            shadow_map_debug_pipeline: {
+                let shadow_map_debug_pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("shadow map debug pipeline layout"),
+                        bind_group_layouts: &[&resources.shadow_map_debug_bgl],
+                        push_constant_ranges: &[],
+                    });
+
+                let vs_fullscreen_tri =
+                    wgpu::include_spirv!("../shaders/compiled/fullscreen_tri.vert.spv");
+                let vs_fullscreen_tri = device.create_shader_module(&vs_fullscreen_tri);
+                let fs_shadow_map_debug =
+                    wgpu::include_spirv!("../shaders/compiled/shadow_map_debug.frag.spv");
+                let fs_shadow_map_debug = device.create_shader_module(&fs_shadow_map_debug);
+
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("shadow map debug pipeline"),
+                    layout: Some(&shadow_map_debug_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &vs_fullscreen_tri,
+                        entry_point: "main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &fs_shadow_map_debug,
+                        entry_point: "main",
+                        targets: &[SHADOW_MAP_DEBUG_FORMAT.into()],
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                })
+            },
             tonemap_pipeline: {
                 let tonemap_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -425,7 +655,10 @@ impl Pipelines {
                 let scene_shadows_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                         label: Some("scene shadows pipeline layout"),
-                        bind_group_layouts: &[shadow_maps.light_projection_bind_group_layout()],
+                        bind_group_layouts: &[
+                            shadow_maps.light_projection_bind_group_layout(),
+                            &resources.main_bgl,
+                        ],
                         push_constant_ranges: &[],
                     });
 
@@ -447,28 +680,30 @@ impl Pipelines {
                     multisample: wgpu::MultisampleState::default(),
                 })
             },
-            ship_shadows_pipeline: {
-                let ship_shadows_pipeline_layout =
+            terrain_chunks_shadows_pipeline: {
+                let terrain_chunks_shadows_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("ship shadows pipeline layout"),
+                        label: Some("terrain chunks shadows pipeline layout"),
                         bind_group_layouts: &[
                             shadow_maps.light_projection_bind_group_layout(),
-                            &resources.ship_bgl,
+                            &resources.main_bgl,
+                            &resources.terrain_chunks_bgl,
                         ],
                         push_constant_ranges: &[],
                     });
 
-                let vs_ship_shadows =
-                    wgpu::include_spirv!("../shaders/compiled/ship_shadows.vert.spv");
-                let vs_ship_shadows = device.create_shader_module(&vs_ship_shadows);
+                let vs_terrain_chunks_shadows =
+                    wgpu::include_spirv!("../shaders/compiled/terrain_chunks_shadows.vert.spv");
+                let vs_terrain_chunks_shadows =
+                    device.create_shader_module(&vs_terrain_chunks_shadows);
 
                 device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("ship shadows pipeline"),
-                    layout: Some(&ship_shadows_pipeline_layout),
+                    label: Some("terrain chunks shadows pipeline"),
+                    layout: Some(&terrain_chunks_shadows_pipeline_layout),
                     vertex: wgpu::VertexState {
-                        module: &vs_ship_shadows,
+                        module: &vs_terrain_chunks_shadows,
                         entry_point: "main",
-                        buffers: &[vertex_buffer_layout.clone()],
+                        buffers: &terrain_chunk_buffer_layouts,
                     },
                     fragment: None,
                     primitive: backface_culling.clone(),
@@ -476,26 +711,26 @@ impl Pipelines {
                     multisample: wgpu::MultisampleState::default(),
                 })
             },
-            land_craft_shadows_pipeline: {
-                let land_craft_shadows_pipeline_layout =
+            vehicle_shadows_pipeline: {
+                let vehicle_shadows_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("land craft shadows pipeline layout"),
+                        label: Some("vehicle shadows pipeline layout"),
                         bind_group_layouts: &[
                             shadow_maps.light_projection_bind_group_layout(),
-                            &resources.land_craft_bgl,
+                            &resources.vehicle_bgl,
                         ],
                         push_constant_ranges: &[],
                     });
 
-                let vs_land_craft_shadows =
-                    wgpu::include_spirv!("../shaders/compiled/land_craft_shadows.vert.spv");
-                let vs_land_craft_shadows = device.create_shader_module(&vs_land_craft_shadows);
+                let vs_vehicle_shadows =
+                    wgpu::include_spirv!("../shaders/compiled/vehicles_shadows.vert.spv");
+                let vs_vehicle_shadows = device.create_shader_module(&vs_vehicle_shadows);
 
                 device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("land craft shadows pipeline"),
-                    layout: Some(&land_craft_shadows_pipeline_layout),
+                    label: Some("vehicle shadows pipeline"),
+                    layout: Some(&vehicle_shadows_pipeline_layout),
                     vertex: wgpu::VertexState {
-                        module: &vs_land_craft_shadows,
+                        module: &vs_vehicle_shadows,
                         entry_point: "main",
                         buffers: &[vertex_buffer_layout.clone()],
                     },
@@ -506,24 +741,13 @@ impl Pipelines {
                 })
             },
             ship_movement_pipeline: {
-                let ship_movement_pipeline_layout =
-                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("ship movement pipeline layout"),
-                        bind_group_layouts: &[
-                            &resources.main_bgl,
-                            &resources.ship_bgl,
-                            &resources.particles_bgl,
-                        ],
-                        push_constant_ranges: &[],
-                    });
-
                 let cs_ship_movement =
                     wgpu::include_spirv!("../shaders/compiled/ship_movement.comp.spv");
                 let cs_ship_movement = device.create_shader_module(&cs_ship_movement);
 
                 device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                     label: Some("ship movement pipeline"),
-                    layout: Some(&ship_movement_pipeline_layout),
+                    layout: Some(&ship_compute_pipeline_layout),
                     module: &cs_ship_movement,
                     entry_point: "main",
                 })
@@ -540,31 +764,169 @@ impl Pipelines {
                     entry_point: "main",
                 })
             },
+            particles_spawn_pipeline: {
+                let cs_particles_spawn =
+                    wgpu::include_spirv!("../shaders/compiled/particles_spawn.comp.spv");
+                let cs_particles_spawn = device.create_shader_module(&cs_particles_spawn);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("particles spawn pipeline"),
+                    layout: Some(&particles_spawn_pipeline_layout),
+                    module: &cs_particles_spawn,
+                    entry_point: "main",
+                })
+            },
+            particles_advance_pipeline: {
+                let cs_particles_advance =
+                    wgpu::include_spirv!("../shaders/compiled/particles_advance.comp.spv");
+                let cs_particles_advance = device.create_shader_module(&cs_particles_advance);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("particles advance pipeline"),
+                    layout: Some(&particles_spawn_pipeline_layout),
+                    module: &cs_particles_advance,
+                    entry_point: "main",
+                })
+            },
             land_craft_movement_pipeline: {
-                let land_craft_movement_pipeline_layout =
+                let cs_land_craft_movement =
+                    wgpu::include_spirv!("../shaders/compiled/land_craft_movement.comp.spv");
+                let cs_land_craft_movement = device.create_shader_module(&cs_land_craft_movement);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("land craft movement pipeline"),
+                    layout: Some(&land_craft_compute_pipeline_layout),
+                    module: &cs_land_craft_movement,
+                    entry_point: "main",
+                })
+            },
+            vehicle_grid_clear_pipeline: {
+                let cs_vehicle_grid_clear =
+                    wgpu::include_spirv!("../shaders/compiled/vehicle_grid_clear.comp.spv");
+                let cs_vehicle_grid_clear = device.create_shader_module(&cs_vehicle_grid_clear);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("vehicle grid clear pipeline"),
+                    layout: Some(&vehicle_grid_pipeline_layout),
+                    module: &cs_vehicle_grid_clear,
+                    entry_point: "main",
+                })
+            },
+            vehicle_grid_count_pipeline: {
+                let cs_vehicle_grid_count =
+                    wgpu::include_spirv!("../shaders/compiled/vehicle_grid_count.comp.spv");
+                let cs_vehicle_grid_count = device.create_shader_module(&cs_vehicle_grid_count);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("vehicle grid count pipeline"),
+                    layout: Some(&vehicle_grid_pipeline_layout),
+                    module: &cs_vehicle_grid_count,
+                    entry_point: "main",
+                })
+            },
+            vehicle_grid_offsets_pipeline: {
+                let cs_vehicle_grid_offsets =
+                    wgpu::include_spirv!("../shaders/compiled/vehicle_grid_offsets.comp.spv");
+                let cs_vehicle_grid_offsets = device.create_shader_module(&cs_vehicle_grid_offsets);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("vehicle grid offsets pipeline"),
+                    layout: Some(&vehicle_grid_pipeline_layout),
+                    module: &cs_vehicle_grid_offsets,
+                    entry_point: "main",
+                })
+            },
+            vehicle_grid_scatter_pipeline: {
+                let cs_vehicle_grid_scatter =
+                    wgpu::include_spirv!("../shaders/compiled/vehicle_grid_scatter.comp.spv");
+                let cs_vehicle_grid_scatter = device.create_shader_module(&cs_vehicle_grid_scatter);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("vehicle grid scatter pipeline"),
+                    layout: Some(&vehicle_grid_pipeline_layout),
+                    module: &cs_vehicle_grid_scatter,
+                    entry_point: "main",
+                })
+            },
+            vehicle_grid_sort_pipeline: {
+                let cs_vehicle_grid_sort =
+                    wgpu::include_spirv!("../shaders/compiled/vehicle_grid_sort.comp.spv");
+                let cs_vehicle_grid_sort = device.create_shader_module(&cs_vehicle_grid_sort);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("vehicle grid sort pipeline"),
+                    layout: Some(&vehicle_grid_pipeline_layout),
+                    module: &cs_vehicle_grid_sort,
+                    entry_point: "main",
+                })
+            },
+            sand_tracks_fill_pipeline: {
+                let sand_tracks_fill_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("land craft movement pipeline layout"),
+                        label: Some("sand tracks fill pipeline layout"),
+                        bind_group_layouts: &[&resources.main_bgl, &resources.sand_tracks_bgl],
+                        push_constant_ranges: &[],
+                    });
+
+                let cs_sand_tracks_fill =
+                    wgpu::include_spirv!("../shaders/compiled/sand_tracks_fill.comp.spv");
+                let cs_sand_tracks_fill = device.create_shader_module(&cs_sand_tracks_fill);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("sand tracks fill pipeline"),
+                    layout: Some(&sand_tracks_fill_pipeline_layout),
+                    module: &cs_sand_tracks_fill,
+                    entry_point: "main",
+                })
+            },
+            sand_tracks_stamp_pipeline: {
+                let sand_tracks_stamp_pipeline_layout =
+                    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("sand tracks stamp pipeline layout"),
                         bind_group_layouts: &[
                             &resources.main_bgl,
                             &resources.land_craft_bgl,
-                            &resources.particles_bgl,
-                            &resources.particles_bgl,
+                            &resources.sand_tracks_bgl,
                         ],
                         push_constant_ranges: &[],
                     });
 
-                let cs_land_craft_movement =
-                    wgpu::include_spirv!("../shaders/compiled/land_craft_movement.comp.spv");
-                let cs_land_craft_movement = device.create_shader_module(&cs_land_craft_movement);
+                let cs_sand_tracks_stamp =
+                    wgpu::include_spirv!("../shaders/compiled/sand_tracks_stamp.comp.spv");
+                let cs_sand_tracks_stamp = device.create_shader_module(&cs_sand_tracks_stamp);
 
                 device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    label: Some("land craft movement pipeline"),
-                    layout: Some(&land_craft_movement_pipeline_layout),
-                    module: &cs_land_craft_movement,
+                    label: Some("sand tracks stamp pipeline"),
+                    layout: Some(&sand_tracks_stamp_pipeline_layout),
+                    module: &cs_sand_tracks_stamp,
                     entry_point: "main",
                 })
             },
-            bake_height_map_pipeline: {
+            sand_saltation_pipeline: {
+                let cs_sand_saltation =
+                    wgpu::include_spirv!("../shaders/compiled/sand_simulation_saltation.comp.spv");
+                let cs_sand_saltation = device.create_shader_module(&cs_sand_saltation);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("sand saltation pipeline"),
+                    layout: Some(&sand_simulation_pipeline_layout),
+                    module: &cs_sand_saltation,
+                    entry_point: "main",
+                })
+            },
+            sand_avalanche_pipeline: {
+                let cs_sand_avalanche =
+                    wgpu::include_spirv!("../shaders/compiled/sand_simulation_avalanche.comp.spv");
+                let cs_sand_avalanche = device.create_shader_module(&cs_sand_avalanche);
+
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("sand avalanche pipeline"),
+                    layout: Some(&sand_simulation_pipeline_layout),
+                    module: &cs_sand_avalanche,
+                    entry_point: "main",
+                })
+            },
+            bake_height_map_pipelines: {
                 let vs_bake_height_map =
                     wgpu::include_spirv!("../shaders/compiled/bake_height_map.vert.spv");
                 let vs_bake_height_map = device.create_shader_module(&vs_bake_height_map);
@@ -573,24 +935,57 @@ impl Pipelines {
                     wgpu::include_spirv!("../shaders/compiled/bake_height_map.frag.spv");
                 let fs_bake_height_map = device.create_shader_module(&fs_bake_height_map);
 
-                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("bake height map pipeline"),
-                    layout: Some(&no_bind_groups_pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &vs_bake_height_map,
-                        entry_point: "main",
-                        buffers: &[vertex_buffer_layout.clone()],
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &fs_bake_height_map,
-                        entry_point: "main",
-                        targets: &[wgpu::TextureFormat::R32Float.into()],
-                    }),
-                    primitive: wgpu::PrimitiveState::default(),
-                    depth_stencil: None,
-                    multisample: wgpu::MultisampleState::default(),
-                })
+                let bake_height_map_pipeline = |format: wgpu::TextureFormat| {
+                    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                        label: Some(&format!("bake height map {:?} pipeline", format)),
+                        layout: Some(&main_bind_group_pipeline_layout),
+                        vertex: wgpu::VertexState {
+                            module: &vs_bake_height_map,
+                            entry_point: "main",
+                            buffers: &[
+                                vertex_buffer_layout.clone(),
+                                // The offset of each sample of the height map.
+                                wgpu::VertexBufferLayout {
+                                    array_stride: std::mem::size_of::<Vec2>() as u64,
+                                    step_mode: wgpu::InputStepMode::Instance,
+                                    attributes: &wgpu::vertex_attr_array![4 => Float2],
+                                },
+                            ],
+                        },
+                        fragment: Some(wgpu::FragmentState {
+                            module: &fs_bake_height_map,
+                            entry_point: "main",
+                            targets: &[
+                                format.into(),
+                                HEIGHT_MAP_NORMALS_FORMAT.into(),
+                                HEIGHT_MAP_UVS_FORMAT.into(),
+                                HEIGHT_MAP_TANGENTS_FORMAT.into(),
+                            ],
+                        }),
+                        primitive: wgpu::PrimitiveState::default(),
+                        depth_stencil: Some(wgpu::DepthStencilState {
+                            // The lowest heights are at a depth of exactly 1.
+                            depth_compare: wgpu::CompareFunction::LessEqual,
+                            ..depth_write.clone()
+                        }),
+                        multisample: wgpu::MultisampleState::default(),
+                    })
+                };
+
+                [
+                    bake_height_map_pipeline(HEIGHT_MAP_FORMATS[0]),
+                    bake_height_map_pipeline(HEIGHT_MAP_FORMATS[1]),
+                ]
             },
         }
     }
+
+    pub fn bake_height_map_pipeline(&self, format: wgpu::TextureFormat) -> &wgpu::RenderPipeline {
+        let index = HEIGHT_MAP_FORMATS
+            .iter()
+            .position(|&height_map_format| height_map_format == format)
+            .unwrap();
+
+        &self.bake_height_map_pipelines[index]
+    }
 }
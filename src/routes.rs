@@ -0,0 +1,152 @@
+use crate::height_field::HeightField;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use ultraviolet::Vec2;
+
+/// The number of loops to generate for terrain that doesn't come with its own.
+const NUM_GENERATED_ROUTES: usize = 8;
+/// Generated waypoints are moved in or out along their loop to keep them off
+/// ground steeper than this, in radians.
+const MAX_WAYPOINT_SLOPE: f32 = 0.5;
+/// How many distances are tried for each waypoint before keeping a steep one.
+const MAX_WAYPOINT_ATTEMPTS: u32 = 8;
+/// How far into the progress along a route to look when working out which way
+/// a craft should face when it's spawned.
+const FACING_LOOK_AHEAD: f32 = 0.05;
+
+/// Closed loops over the XZ plane for the land craft to drive around. Each
+/// route is a list of waypoints that are joined up with a Catmull-Rom spline,
+/// both here and in `land_craft/movement.comp`.
+pub struct Routes {
+    routes: Vec<Vec<Vec2>>,
+}
+
+impl Routes {
+    /// Routes with fewer than 2 waypoints are skipped.
+    pub fn new(routes: Vec<Vec<Vec2>>) -> Self {
+        Self {
+            routes: routes
+                .into_iter()
+                .filter(|waypoints| waypoints.len() >= 2)
+                .collect(),
+        }
+    }
+
+    /// Wobbly loops scattered over the scene, avoiding steep ground where they
+    /// can. The same terrain always gives the same routes.
+    pub fn generate(bounds: primitives::SceneBounds, height_field: &HeightField) -> Self {
+        use std::f32::consts::PI;
+
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let center = bounds.center();
+        let half_size = bounds.half_size();
+
+        let routes = (0..NUM_GENERATED_ROUTES)
+            .map(|_| {
+                let route_center = center
+                    + Vec2::new(
+                        rng.gen_range(-0.5..=0.5) * half_size,
+                        rng.gen_range(-0.5..=0.5) * half_size,
+                    );
+                let radius = rng.gen_range(0.15..=0.4) * half_size;
+                let num_waypoints = rng.gen_range(6..=10);
+                // Go around either clockwise or anticlockwise.
+                let direction = if rng.gen() { 1.0 } else { -1.0 };
+
+                (0..num_waypoints)
+                    .map(|i| {
+                        let angle = direction * i as f32 / num_waypoints as f32 * PI * 2.0;
+
+                        let mut random_point = || {
+                            let distance = radius * rng.gen_range(0.7..=1.3);
+                            let point =
+                                route_center + Vec2::new(angle.cos(), angle.sin()) * distance;
+                            point.clamped(bounds.min, bounds.max)
+                        };
+
+                        let mut point = random_point();
+
+                        for _ in 1..MAX_WAYPOINT_ATTEMPTS {
+                            if height_field.slope_at(point.x, point.y) <= MAX_WAYPOINT_SLOPE {
+                                break;
+                            }
+
+                            point = random_point();
+                        }
+
+                        point
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self::new(routes)
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// The number of waypoints in a route, which is also how far the progress
+    /// along it goes before looping back around to 0.
+    pub fn num_waypoints(&self, route: usize) -> usize {
+        self.routes[route].len()
+    }
+
+    /// A point on a route's spline, where each whole number of progress is the
+    /// next waypoint.
+    pub fn point_at(&self, route: usize, progress: f32) -> Vec2 {
+        let waypoints = &self.routes[route];
+        let len = waypoints.len();
+
+        let index = progress.floor() as usize;
+        let t = progress.fract();
+
+        let p0 = waypoints[(index + len - 1) % len];
+        let p1 = waypoints[index % len];
+        let p2 = waypoints[(index + 1) % len];
+        let p3 = waypoints[(index + 2) % len];
+
+        catmull_rom(p0, p1, p2, p3, t)
+    }
+
+    /// The angle around the y axis that a craft at this point of the route
+    /// should be facing in.
+    pub fn facing_at(&self, route: usize, progress: f32) -> f32 {
+        let direction =
+            self.point_at(route, progress + FACING_LOOK_AHEAD) - self.point_at(route, progress);
+        direction.y.atan2(direction.x)
+    }
+
+    /// All the waypoints one after the other, along with where each route's
+    /// waypoints start and how many there are.
+    pub fn flatten(&self) -> (Vec<Vec2>, Vec<primitives::Route>) {
+        let mut waypoints = Vec::new();
+        let mut routes = Vec::new();
+
+        for route in &self.routes {
+            routes.push(primitives::Route {
+                start: waypoints.len() as u32,
+                len: route.len() as u32,
+            });
+            waypoints.extend_from_slice(route);
+        }
+
+        (waypoints, routes)
+    }
+}
+
+fn catmull_rom(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, t: f32) -> Vec2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
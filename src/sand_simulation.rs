@@ -0,0 +1,165 @@
+use crate::dispatch_count;
+use crate::resources_and_pipelines::{Pipelines, RenderResources};
+
+const SIZE: u32 = 512;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// Simulates the wind blowing sand over the dunes, storing how far the sand
+/// has moved up or down from the baked height map.
+///
+/// Storage textures can't be read and written in the same pass, so each step
+/// writes into a second texture which is then copied back.
+pub struct SandSimulation {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    new_texture: wgpu::Texture,
+    new_texture_view: wgpu::TextureView,
+    /// The height map is baked with the main bind group, which includes this
+    /// simulation's texture, so this is only created once the height map is set.
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl SandSimulation {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let descriptor = |label, usage| wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage,
+        };
+
+        let texture = device.create_texture(&descriptor(
+            "sand offset texture",
+            wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        ));
+
+        let new_texture = device.create_texture(&descriptor(
+            "new sand offset texture",
+            wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC,
+        ));
+
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            new_texture_view: new_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            new_texture,
+            bind_group: None,
+        }
+    }
+
+    /// The simulation runs on top of the height map, so it needs to be told when
+    /// that is baked.
+    pub fn set_height_map(
+        &mut self,
+        device: &wgpu::Device,
+        resources: &RenderResources,
+        height_map_texture: &wgpu::TextureView,
+    ) {
+        self.bind_group = Some(create_bind_group(
+            device,
+            resources,
+            height_map_texture,
+            &self.new_texture_view,
+        ));
+    }
+
+    /// Blow sand downwind, then let it slide down any slopes that have become
+    /// too steep.
+    pub fn update(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipelines: &Pipelines,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let simulation_bind_group = self
+            .bind_group
+            .as_ref()
+            .expect("The height map needs to be set before simulating");
+
+        let steps = [
+            ("sand saltation pass", &pipelines.sand_saltation_pipeline),
+            ("sand avalanche pass", &pipelines.sand_avalanche_pipeline),
+        ];
+
+        for &(label, pipeline) in &steps {
+            let mut compute_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some(label) });
+
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.set_bind_group(1, simulation_bind_group, &[]);
+            compute_pass.dispatch(dispatch_count(SIZE, 8), dispatch_count(SIZE, 8), 1);
+
+            drop(compute_pass);
+
+            encoder.copy_texture_to_texture(
+                wgpu::TextureCopyView {
+                    texture: &self.new_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::TextureCopyView {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::Extent3d {
+                    width: SIZE,
+                    height: SIZE,
+                    depth: 1,
+                },
+            );
+        }
+    }
+
+    /// Put all the sand back where it was on the baked terrain.
+    pub fn reset(&self, queue: &wgpu::Queue) {
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &vec![0; (SIZE * SIZE * 4) as usize],
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: SIZE * 4,
+                rows_per_image: 0,
+            },
+            wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+        );
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    resources: &RenderResources,
+    height_map_texture: &wgpu::TextureView,
+    new_texture_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("sand simulation bind group"),
+        layout: &resources.sand_simulation_bgl,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(height_map_texture),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(new_texture_view),
+            },
+        ],
+    })
+}
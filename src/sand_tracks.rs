@@ -0,0 +1,140 @@
+use crate::dispatch_count;
+use crate::resources_and_pipelines::{Pipelines, RenderResources};
+
+const SIZE: u32 = 1024;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// The depth of the tracks that the land craft press into the sand, over the
+/// scene bounds. The tracks slowly fill back in over time.
+///
+/// Each update writes the new depths into a buffer which is then copied back
+/// into the texture. Where the tracks of several craft overlap, the deepest
+/// is kept with an atomic max over the bits of the depths, which storage
+/// textures don't support everywhere. Because the depths are never negative,
+/// their bits sort in the same order as the depths do.
+pub struct SandTracks {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    new_depths: wgpu::Buffer,
+    new_depths_bind_group: wgpu::BindGroup,
+}
+
+impl SandTracks {
+    pub fn new(device: &wgpu::Device, resources: &RenderResources) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("sand tracks texture"),
+            size: wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        let new_depths = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("new sand tracks buffer"),
+            size: (SIZE * SIZE * 4) as u64,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let new_depths_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("new sand tracks bind group"),
+            layout: &resources.sand_tracks_bgl,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: new_depths.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            new_depths,
+            new_depths_bind_group,
+        }
+    }
+
+    /// Fill in the existing tracks a little and stamp new ones under each land
+    /// craft, for each of the land craft bind groups and their number of craft.
+    pub fn update<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipelines: &Pipelines,
+        bind_group: &wgpu::BindGroup,
+        tracks: impl IntoIterator<Item = (&'a wgpu::BindGroup, u32)>,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("sand tracks fill pass"),
+        });
+
+        compute_pass.set_pipeline(&pipelines.sand_tracks_fill_pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.new_depths_bind_group, &[]);
+        compute_pass.dispatch(dispatch_count(SIZE, 8), dispatch_count(SIZE, 8), 1);
+
+        drop(compute_pass);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("sand tracks stamp pass"),
+        });
+
+        compute_pass.set_pipeline(&pipelines.sand_tracks_stamp_pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.set_bind_group(2, &self.new_depths_bind_group, &[]);
+
+        for (land_craft_bind_group, num_land_craft) in tracks {
+            compute_pass.set_bind_group(1, land_craft_bind_group, &[]);
+            compute_pass.dispatch(dispatch_count(num_land_craft, 64), 1, 1);
+        }
+
+        drop(compute_pass);
+
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &self.new_depths,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: SIZE * 4,
+                    rows_per_image: 0,
+                },
+            },
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+        );
+    }
+
+    /// Smooth out all the tracks, such as when the terrain changes.
+    pub fn clear(&self, queue: &wgpu::Queue) {
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &vec![0; (SIZE * SIZE * 4) as usize],
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: SIZE * 4,
+                rows_per_image: 0,
+            },
+            wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+        );
+    }
+}
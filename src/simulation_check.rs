@@ -0,0 +1,657 @@
+use crate::collision_counter::CollisionCounter;
+use crate::model_loading::Scene;
+use crate::reference_simulation::{move_land_craft, move_particle, move_ship};
+use crate::resource_creation::create_main_bind_group;
+use crate::resources_and_pipelines::{Pipelines, RenderResources};
+use crate::vehicles::{Movement, SpawnContext, VehicleType, Vehicles};
+use crate::{sand_simulation, sand_tracks};
+use cascaded_shadow_maps::CascadedShadowMaps;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+use ultraviolet::{Vec2, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+const NUM_STEPS: u32 = 120;
+const SEED: u64 = 0;
+const DELTA_TIME: f32 = 1.0 / 60.0;
+
+// The shaders are allowed to be a lot less precise than the cpu with
+// trigonometry, so these are fairly loose.
+const POSITION_TOLERANCE: f32 = 1.0e-3;
+const ANGLE_TOLERANCE: f32 = 2.0e-3;
+const ROTATION_MATRIX_TOLERANCE: f32 = 2.0e-2;
+
+/// Request a device from the Vulkan backend, so that a software adapter such as
+/// lavapipe can be picked by pointing `VK_ICD_FILENAMES` at its icd file.
+fn request_device() -> (wgpu::Device, wgpu::Queue) {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::VULKAN);
+
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+    }));
+
+    let adapter = adapter.expect(
+        "There isn't a Vulkan adapter. Point `VK_ICD_FILENAMES` at the icd file of a software \
+         adapter such as lavapipe to run the simulation check without a gpu",
+    );
+
+    pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("device"),
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+        },
+        None,
+    ))
+    .expect("'request_device' failed")
+}
+
+/// Everything needed to step the vehicle and particle compute shaders for each
+/// vehicle type in `vehicles.json`, without a window.
+struct Simulation {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipelines: Pipelines,
+    scene: Scene,
+    settings: primitives::Settings,
+    bind_group: wgpu::BindGroup,
+    time_buffer: wgpu::Buffer,
+    collision_counter: CollisionCounter,
+    vehicles: Vec<Vehicles>,
+}
+
+impl Simulation {
+    fn new(device: wgpu::Device, queue: wgpu::Queue, seed: u64) -> Self {
+        let resources = RenderResources::new(&device);
+        let cascaded_shadow_maps = CascadedShadowMaps::new(&device, 1024);
+        let pipelines = Pipelines::new(
+            &device,
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+            &resources,
+            &cascaded_shadow_maps,
+        );
+
+        let scene = Scene::load(
+            include_bytes!("../models/dune.glb"),
+            &device,
+            &queue,
+            &resources,
+            1024,
+        )
+        .unwrap();
+        let scene_bounds = scene.xz_bounds();
+
+        // Check everything that can be turned on at once.
+        let settings = primitives::Settings {
+            ambient_lighting: Vec3::zero(),
+            mode: primitives::Mode::Full as u32,
+            specular_factor: 1.0,
+            ship_movement_bounds: scene_bounds.max_ship_movement_bounds(),
+            wind_direction: 0.0,
+            wind_strength: 0.0,
+            flocking: 1,
+            boid_neighbour_radius: 0.1,
+            boid_separation_weight: 1.5,
+            boid_alignment_weight: 1.0,
+            boid_cohesion_weight: 1.0,
+            ship_clearance: 0.2,
+            land_craft_avoidance: 1,
+            land_craft_radius: 0.03,
+        };
+
+        let uniform_buffer = |label, contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                contents,
+            })
+        };
+
+        let camera_buffer = uniform_buffer(
+            "camera buffer",
+            bytemuck::bytes_of(&scene.create_camera(1, 1)),
+        );
+        let settings_buffer = uniform_buffer("settings buffer", bytemuck::bytes_of(&settings));
+        let time_buffer = uniform_buffer(
+            "time buffer",
+            bytemuck::bytes_of(&primitives::Time::default()),
+        );
+        let scene_bounds_buffer =
+            uniform_buffer("scene bounds buffer", bytemuck::bytes_of(&scene_bounds));
+        let terrain_material_buffer = uniform_buffer(
+            "terrain material buffer",
+            bytemuck::bytes_of(&primitives::TerrainMaterial::default()),
+        );
+
+        // Both of these start out flat, so the ground is just the height field.
+        let sand_tracks = sand_tracks::SandTracks::new(&device, &resources);
+        sand_tracks.clear(&queue);
+
+        let sand_simulation = sand_simulation::SandSimulation::new(&device);
+        sand_simulation.reset(&queue);
+
+        let bind_group = create_main_bind_group(
+            &device,
+            &resources,
+            &camera_buffer,
+            &scene.sun_buffer,
+            &settings_buffer,
+            &time_buffer,
+            &scene_bounds_buffer,
+            &sand_tracks.view,
+            &sand_simulation.view,
+            &terrain_material_buffer,
+        );
+
+        let (height_map, height_map_normals) = upload_height_field(&device, &queue, &scene);
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let collision_counter = CollisionCounter::new(&device);
+
+        let context = SpawnContext {
+            device: &device,
+            queue: &queue,
+            resources: &resources,
+            height_field: &scene.height_field,
+            height_map: &height_map,
+            height_map_normals: &height_map_normals,
+            settings: &settings,
+            scene_bounds,
+            sand_colour: Vec3::one(),
+            routes: &scene.routes,
+            collisions_buffer: &collision_counter.buffer,
+        };
+
+        let vehicles = VehicleType::load_all()
+            .unwrap()
+            .into_iter()
+            .map(|vehicle_type| Vehicles::new(vehicle_type, &context, &mut rng))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        Self {
+            device,
+            queue,
+            pipelines,
+            scene,
+            settings,
+            bind_group,
+            time_buffer,
+            collision_counter,
+            vehicles,
+        }
+    }
+
+    fn set_time(&self, step: u32) -> primitives::Time {
+        let time = primitives::Time {
+            time_since_start: step as f32 * DELTA_TIME,
+            delta_time: DELTA_TIME,
+            frame: step,
+        };
+
+        self.queue
+            .write_buffer(&self.time_buffer, 0, bytemuck::bytes_of(&time));
+        self.collision_counter.reset(&self.queue);
+
+        time
+    }
+
+    fn move_particles(&self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("particles check encoder"),
+            });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("particles check compute pass"),
+        });
+
+        for vehicles in &self.vehicles {
+            vehicles.move_particles(&mut compute_pass, &self.pipelines, &self.bind_group);
+        }
+
+        drop(compute_pass);
+
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// The same passes as each step in `main.rs`.
+    fn step_vehicles(&self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("vehicles check encoder"),
+            });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("vehicles check compute pass"),
+        });
+
+        for vehicles in &self.vehicles {
+            vehicles.step(
+                &mut compute_pass,
+                &self.pipelines,
+                &self.bind_group,
+                &self.settings,
+            );
+        }
+
+        drop(compute_pass);
+
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// The bits of every vehicle and particle.
+    fn snapshot(&self) -> Vec<Vec<u32>> {
+        let mut snapshot = Vec::new();
+
+        for vehicles in &self.vehicles {
+            snapshot.push(
+                match VehicleState::read(&self.device, &self.queue, vehicles) {
+                    VehicleState::Flying(ships) => bytemuck::cast_slice(&ships).to_vec(),
+                    VehicleState::Driving(crafts) => bytemuck::cast_slice(&crafts).to_vec(),
+                },
+            );
+
+            for emitter in 0..vehicles.vehicle_type.emitters.len() {
+                let (particles_buffer, _, num_particles) = vehicles.particles(emitter);
+                let particles: Vec<primitives::Particle> =
+                    read_buffer(&self.device, &self.queue, particles_buffer, num_particles);
+                snapshot.push(bytemuck::cast_slice(&particles).to_vec());
+            }
+        }
+
+        snapshot
+    }
+}
+
+/// Step the vehicle and particle compute shaders and compare every step against
+/// `reference_simulation`, starting each step from the gpu's state so that small
+/// differences don't build up.
+#[test]
+#[ignore = "needs a Vulkan adapter, run with `cargo test -- --ignored`"]
+fn gpu_matches_cpu_reference() {
+    let (device, queue) = request_device();
+
+    let simulation = Simulation::new(device, queue, SEED);
+    let (device, queue) = (&simulation.device, &simulation.queue);
+
+    let mut errors = Errors::default();
+
+    for step in 1..=NUM_STEPS {
+        let time = simulation.set_time(step);
+
+        // The particles are checked on their own, as the vehicles spawn new
+        // particles over the top of them.
+
+        let mut emitters = Vec::new();
+
+        for vehicles in &simulation.vehicles {
+            for emitter in 0..vehicles.vehicle_type.emitters.len() {
+                let (particles_buffer, info_buffer, num_particles) = vehicles.particles(emitter);
+
+                let particles: Vec<primitives::Particle> =
+                    read_buffer(device, queue, particles_buffer, num_particles);
+                let info: primitives::ParticlesBufferInfo =
+                    read_buffer(device, queue, info_buffer, 1)[0];
+
+                emitters.push((particles_buffer, num_particles, particles, info));
+            }
+        }
+
+        simulation.move_particles();
+
+        for (particles_buffer, num_particles, particles, info) in &emitters {
+            let gpu_particles: Vec<primitives::Particle> =
+                read_buffer(device, queue, particles_buffer, *num_particles);
+
+            for (particle, gpu_particle) in particles.iter().zip(&gpu_particles) {
+                let cpu_particle = move_particle(*particle, info._particle_duration, time);
+
+                errors.check_vec3(
+                    "particle position",
+                    cpu_particle.position,
+                    gpu_particle.position,
+                    POSITION_TOLERANCE,
+                );
+                errors.check(
+                    "particle time alive",
+                    cpu_particle.time_spawned,
+                    gpu_particle.time_spawned,
+                    POSITION_TOLERANCE,
+                );
+            }
+        }
+
+        let states: Vec<_> = simulation
+            .vehicles
+            .iter()
+            .map(|vehicles| VehicleState::read(device, queue, vehicles))
+            .collect();
+
+        simulation.step_vehicles();
+
+        for (vehicles, state) in simulation.vehicles.iter().zip(&states) {
+            let settings = &simulation.settings;
+            let scene = &simulation.scene;
+
+            match (
+                vehicles.vehicle_type.movement,
+                state,
+                VehicleState::read(device, queue, vehicles),
+            ) {
+                (
+                    Movement::Flying { bob_amplitude, .. },
+                    VehicleState::Flying(ships),
+                    VehicleState::Flying(gpu_ships),
+                ) => check_ships(
+                    &mut errors,
+                    ships,
+                    &gpu_ships,
+                    bob_amplitude,
+                    settings,
+                    time,
+                    scene,
+                ),
+                (_, VehicleState::Driving(crafts), VehicleState::Driving(gpu_crafts)) => {
+                    check_land_craft(&mut errors, crafts, &gpu_crafts, settings, time, scene)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    errors.assert_within_tolerance();
+}
+
+/// Two runs from the same seed should give exactly the same vehicles and
+/// particles, down to the bit.
+#[test]
+#[ignore = "needs a Vulkan adapter, run with `cargo test -- --ignored`"]
+fn seeded_runs_are_identical() {
+    let (device, queue) = request_device();
+    let first = Simulation::new(device, queue, SEED);
+
+    let (device, queue) = request_device();
+    let second = Simulation::new(device, queue, SEED);
+
+    assert_eq!(first.snapshot(), second.snapshot());
+
+    for step in 1..=NUM_STEPS {
+        for simulation in &[&first, &second] {
+            simulation.set_time(step);
+            simulation.move_particles();
+            simulation.step_vehicles();
+        }
+
+        assert_eq!(
+            first.snapshot(),
+            second.snapshot(),
+            "The runs differed after {} steps",
+            step
+        );
+    }
+}
+
+/// The vehicles of one type, read back from the gpu.
+enum VehicleState {
+    Flying(Vec<primitives::Ship>),
+    Driving(Vec<primitives::LandCraft>),
+}
+
+impl VehicleState {
+    fn read(device: &wgpu::Device, queue: &wgpu::Queue, vehicles: &Vehicles) -> Self {
+        let buffers = vehicles.buffers();
+        let (buffer, num) = (buffers.vehicles(), buffers.num_vehicles());
+
+        match vehicles.vehicle_type.movement {
+            Movement::Flying { .. } => Self::Flying(read_buffer(device, queue, buffer, num)),
+            Movement::Driving { .. } => Self::Driving(read_buffer(device, queue, buffer, num)),
+        }
+    }
+}
+
+fn check_ships(
+    errors: &mut Errors,
+    ships: &[primitives::Ship],
+    gpu_ships: &[primitives::Ship],
+    bob_amplitude: f32,
+    settings: &primitives::Settings,
+    time: primitives::Time,
+    scene: &Scene,
+) {
+    for (index, gpu_ship) in gpu_ships.iter().enumerate() {
+        let cpu_ship = move_ship(
+            ships,
+            index,
+            bob_amplitude,
+            settings,
+            time,
+            scene.xz_bounds(),
+            &scene.height_field,
+        );
+
+        errors.check_vec3(
+            "ship position",
+            cpu_ship.position,
+            gpu_ship.position,
+            POSITION_TOLERANCE,
+        );
+        errors.check_angle(
+            "ship facing",
+            cpu_ship.y_rotation,
+            gpu_ship.y_rotation,
+            ANGLE_TOLERANCE,
+        );
+        errors.check("ship roll", cpu_ship.roll, gpu_ship.roll, ANGLE_TOLERANCE);
+        errors.check_rotation_matrix(
+            "ship rotation matrix",
+            cpu_ship._rotation_matrix,
+            gpu_ship._rotation_matrix,
+        );
+    }
+}
+
+fn check_land_craft(
+    errors: &mut Errors,
+    crafts: &[primitives::LandCraft],
+    gpu_crafts: &[primitives::LandCraft],
+    settings: &primitives::Settings,
+    time: primitives::Time,
+    scene: &Scene,
+) {
+    for (index, gpu_craft) in gpu_crafts.iter().enumerate() {
+        let cpu_craft = move_land_craft(
+            crafts,
+            index,
+            settings,
+            time,
+            &scene.routes,
+            &scene.height_field,
+        );
+
+        errors.check_vec3(
+            "land craft position",
+            cpu_craft.position,
+            gpu_craft.position,
+            POSITION_TOLERANCE,
+        );
+        errors.check_angle(
+            "land craft facing",
+            cpu_craft.facing,
+            gpu_craft.facing,
+            ANGLE_TOLERANCE,
+        );
+        errors.check(
+            "land craft route progress",
+            cpu_craft.route_progress,
+            gpu_craft.route_progress,
+            POSITION_TOLERANCE,
+        );
+        errors.check_rotation_matrix(
+            "land craft rotation matrix",
+            cpu_craft._rotation_matrix,
+            gpu_craft._rotation_matrix,
+        );
+    }
+}
+
+/// Upload the cpu-side height field, so that the gpu samples exactly the same
+/// heights. The normals are taken at the center of each texel.
+fn upload_height_field(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    scene: &Scene,
+) -> (wgpu::TextureView, wgpu::TextureView) {
+    let height_field = &scene.height_field;
+    let size = height_field.size();
+    let scene_bounds = scene.xz_bounds();
+    let texel_size = (scene_bounds.max - scene_bounds.min) / size as f32;
+
+    let normals: Vec<Vec4> = (0..size * size)
+        .map(|i| {
+            let texel = Vec2::new((i % size) as f32 + 0.5, (i / size) as f32 + 0.5);
+            let position = scene_bounds.min + texel * texel_size;
+            let normal = height_field.normal_at(position.x, position.y);
+            Vec4::new(normal.x, normal.y, normal.z, 0.0)
+        })
+        .collect();
+
+    let texture = |label, format, data: &[u8]| {
+        device
+            .create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: size,
+                        height: size,
+                        depth: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsage::SAMPLED,
+                },
+                data,
+            )
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+
+    (
+        texture(
+            "height map texture",
+            wgpu::TextureFormat::R32Float,
+            bytemuck::cast_slice(height_field.heights()),
+        ),
+        texture(
+            "height map normals texture",
+            wgpu::TextureFormat::Rgba32Float,
+            bytemuck::cast_slice(&normals),
+        ),
+    )
+}
+
+/// Copy the first `len` items of a storage buffer into one that can be mapped,
+/// and wait for it.
+fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    len: u32,
+) -> Vec<T> {
+    let size = (std::mem::size_of::<T>() * len as usize) as u64;
+
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("simulation check readback buffer"),
+        size,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("simulation check readback encoder"),
+    });
+
+    encoder.copy_buffer_to_buffer(buffer, 0, &readback_buffer, 0, size);
+
+    queue.submit(Some(encoder.finish()));
+
+    let slice = readback_buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).unwrap();
+
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    readback_buffer.unmap();
+
+    data
+}
+
+/// The largest difference between the cpu and gpu for each value, and how many
+/// times each one was out of tolerance.
+#[derive(Default)]
+struct Errors {
+    values: BTreeMap<&'static str, (f32, u32)>,
+}
+
+impl Errors {
+    fn record(&mut self, name: &'static str, error: f32, tolerance: f32) {
+        let (largest, num_failures) = self.values.entry(name).or_default();
+        *largest = largest.max(error);
+
+        if error.is_nan() || error > tolerance {
+            *num_failures += 1;
+        }
+    }
+
+    fn check(&mut self, name: &'static str, cpu: f32, gpu: f32, tolerance: f32) {
+        self.record(name, (cpu - gpu).abs(), tolerance);
+    }
+
+    fn check_vec3(&mut self, name: &'static str, cpu: Vec3, gpu: Vec3, tolerance: f32) {
+        self.record(name, (cpu - gpu).abs().component_max(), tolerance);
+    }
+
+    /// Angles that are a full turn apart are the same.
+    fn check_angle(&mut self, name: &'static str, cpu: f32, gpu: f32, tolerance: f32) {
+        let turn = std::f32::consts::PI * 2.0;
+        let difference = (cpu - gpu).rem_euclid(turn);
+        self.record(name, difference.min(turn - difference), tolerance);
+    }
+
+    fn check_rotation_matrix(&mut self, name: &'static str, cpu: [Vec4; 3], gpu: [Vec4; 3]) {
+        // The fourth component of each column is padding.
+        let error = cpu
+            .iter()
+            .zip(&gpu)
+            .map(|(&cpu, &gpu)| (Vec3::from(cpu) - Vec3::from(gpu)).abs().component_max())
+            .fold(0.0, f32::max);
+
+        self.record(name, error, ROTATION_MATRIX_TOLERANCE);
+    }
+
+    fn assert_within_tolerance(&self) {
+        let failures: Vec<String> = self
+            .values
+            .iter()
+            .filter(|(_, (_, num_failures))| *num_failures > 0)
+            .map(|(name, (largest, num_failures))| {
+                format!(
+                    "{}: largest difference {:e}, {} out of tolerance",
+                    name, largest, num_failures
+                )
+            })
+            .collect();
+
+        assert!(
+            failures.is_empty(),
+            "The gpu simulation differed from the cpu reference:\n{}",
+            failures.join("\n")
+        );
+    }
+}
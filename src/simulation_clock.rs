@@ -0,0 +1,148 @@
+/// The length of each step of the vehicle, particle and sand simulations, so
+/// that they behave the same regardless of the frame rate.
+const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+/// If frames take longer than this many steps, the simulation slows down
+/// instead of falling further and further behind.
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+/// Decides how many fixed steps the simulations need to take each frame, from
+/// the real time that has passed, scaled by `time_scale`.
+pub struct SimulationClock {
+    pub paused: bool,
+    pub time_scale: f32,
+    time_since_start: f32,
+    frame: u32,
+    unsimulated_time: f32,
+    last_frame: instant::Instant,
+    step_requested: bool,
+}
+
+impl SimulationClock {
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            time_scale: 1.0,
+            time_since_start: 0.0,
+            frame: 0,
+            unsimulated_time: 0.0,
+            last_frame: instant::Instant::now(),
+            step_requested: false,
+        }
+    }
+
+    /// Take a single step on the next frame, even while paused.
+    pub fn request_step(&mut self) {
+        self.step_requested = true;
+    }
+
+    pub fn time_since_start(&self) -> f32 {
+        self.time_since_start
+    }
+
+    /// The times that each step this frame should be simulated with, in order.
+    pub fn advance(&mut self) -> Vec<primitives::Time> {
+        self.advance_to(instant::Instant::now())
+    }
+
+    /// The same as `advance`, for a frame that starts at `now`.
+    fn advance_to(&mut self, now: instant::Instant) -> Vec<primitives::Time> {
+        if !self.paused {
+            self.unsimulated_time += (now - self.last_frame).as_secs_f32() * self.time_scale;
+        }
+
+        self.last_frame = now;
+
+        let mut num_steps = 0;
+
+        while self.unsimulated_time >= FIXED_TIMESTEP {
+            if num_steps == MAX_STEPS_PER_FRAME {
+                self.unsimulated_time = 0.0;
+                break;
+            }
+
+            self.unsimulated_time -= FIXED_TIMESTEP;
+            num_steps += 1;
+        }
+
+        if self.step_requested {
+            self.step_requested = false;
+            num_steps = num_steps.max(1);
+        }
+
+        (0..num_steps)
+            .map(|_| {
+                self.time_since_start += FIXED_TIMESTEP;
+                self.frame += 1;
+
+                primitives::Time {
+                    time_since_start: self.time_since_start,
+                    delta_time: FIXED_TIMESTEP,
+                    frame: self.frame,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Advance the clock by a number of fixed steps, plus half a step so that
+    /// rounding doesn't drop one.
+    fn advance_by_steps(clock: &mut SimulationClock, steps: f32) -> Vec<primitives::Time> {
+        let now = clock.last_frame + Duration::from_secs_f32((steps + 0.5) * FIXED_TIMESTEP);
+        clock.advance_to(now)
+    }
+
+    #[test]
+    fn paused_clock_takes_no_steps() {
+        let mut clock = SimulationClock::new();
+        clock.paused = true;
+
+        assert!(advance_by_steps(&mut clock, 4.0).is_empty());
+        assert!(advance_by_steps(&mut clock, 4.0).is_empty());
+        assert_eq!(clock.time_since_start(), 0.0);
+    }
+
+    #[test]
+    fn requested_step_takes_exactly_one_step() {
+        let mut clock = SimulationClock::new();
+        clock.paused = true;
+        clock.request_step();
+
+        let steps = advance_by_steps(&mut clock, 4.0);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].frame, 1);
+        assert_eq!(steps[0].delta_time, FIXED_TIMESTEP);
+
+        // The request only lasts for one frame.
+        assert!(advance_by_steps(&mut clock, 4.0).is_empty());
+    }
+
+    #[test]
+    fn time_scale_scales_the_number_of_steps() {
+        let mut clock = SimulationClock::new();
+        assert_eq!(advance_by_steps(&mut clock, 2.0).len(), 2);
+
+        let mut clock = SimulationClock::new();
+        clock.time_scale = 3.0;
+        assert_eq!(advance_by_steps(&mut clock, 2.0).len(), 7);
+
+        let mut clock = SimulationClock::new();
+        clock.time_scale = 0.5;
+        assert_eq!(advance_by_steps(&mut clock, 4.0).len(), 2);
+    }
+
+    #[test]
+    fn steps_are_capped_each_frame() {
+        let mut clock = SimulationClock::new();
+
+        let steps = advance_by_steps(&mut clock, MAX_STEPS_PER_FRAME as f32 * 10.0);
+        assert_eq!(steps.len(), MAX_STEPS_PER_FRAME as usize);
+
+        // The time that couldn't be simulated is dropped rather than caught up on.
+        assert_eq!(advance_by_steps(&mut clock, 1.0).len(), 1);
+    }
+}
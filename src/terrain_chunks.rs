@@ -0,0 +1,519 @@
+use crate::height_field::HeightField;
+use crate::resource_creation::HeightMap;
+use crate::resources_and_pipelines::RenderResources;
+use crate::terrain_generation::DuneHeights;
+use crate::INDEX_FORMAT;
+use primitives::TerrainChunk;
+use std::collections::HashMap;
+use std::ops::Range;
+use ultraviolet::{Vec2, Vec3};
+use wgpu::util::DeviceExt;
+
+/// The number of chunks along each side of the scene bounds.
+const CHUNKS_PER_SIDE: u32 = 16;
+/// The number of chunks along each side of the area drawn around the camera,
+/// which reaches past the scene bounds.
+const VIEW_CHUNKS: u32 = 32;
+/// The number of quads along each side of a chunk at the highest level of
+/// detail. This needs to match `CHUNK_RESOLUTION` in `terrain_chunks.glsl`.
+const CHUNK_RESOLUTION: u32 = 64;
+/// Each level of detail halves the number of quads along each side.
+const NUM_LODS: usize = 5;
+/// Chunks closer than this many chunk widths are drawn at the highest level of
+/// detail, with each level after that covering twice the distance.
+const LOD_DISTANCE: f32 = 2.0;
+/// The number of heights along each side of a streamed tile. That's one for
+/// each vertex at the highest level of detail, plus a border around them for
+/// the normals.
+const TILE_SIZE: u32 = CHUNK_RESOLUTION + 3;
+/// Every chunk in view can have a tile, so the atlas never runs out of room.
+const ATLAS_TILES_PER_SIDE: u32 = VIEW_CHUNKS;
+/// The most heights to sample each frame when refining tiles, which is enough
+/// for two tiles at the highest level of detail.
+const STREAMING_BUDGET: u32 = TILE_SIZE * TILE_SIZE * 2;
+
+/// Where the heights of the chunks outside the scene bounds are streamed from.
+pub trait HeightSource {
+    fn height(&self, position: Vec2) -> f32;
+}
+
+/// The height field is clamped to its edges, so the terrain carries on flat
+/// past the scene bounds.
+impl HeightSource for HeightField {
+    fn height(&self, position: Vec2) -> f32 {
+        self.height_at(position.x, position.y)
+    }
+}
+
+/// The generated dunes carry on forever.
+impl HeightSource for DuneHeights {
+    fn height(&self, position: Vec2) -> f32 {
+        DuneHeights::height(self, position)
+    }
+}
+
+struct GridMesh {
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    num_indices: u32,
+}
+
+/// A tile of streamed heights in the atlas.
+struct Tile {
+    slot: u32,
+    /// The level of detail that the heights were sampled at. Chunks are never
+    /// drawn at a finer level than their tile.
+    lod: u32,
+}
+
+/// Draws the terrain as a grid of chunks around the camera, where chunks
+/// further from the camera use coarser meshes. Chunks are drawn as instances of
+/// a flat grid mesh, with the heights sampled in the vertex shader.
+///
+/// Chunks inside the scene bounds use the height map, along with the uvs and
+/// tangents of the terrain mesh that were baked into it. Chunks outside of them
+/// have their heights streamed in from a `HeightSource` as the camera moves, so
+/// the terrain isn't limited to the height map. Each of those gets a coarse tile
+/// straight away, then finer tiles as the budget allows, closest first.
+pub struct TerrainChunks {
+    meshes: Vec<GridMesh>,
+    instances: wgpu::Buffer,
+    lod_ranges: [Range<u32>; NUM_LODS],
+    tile_atlas: wgpu::Texture,
+    tile_atlas_view: wgpu::TextureView,
+    /// Indexed by chunk, counting from the corner of the scene bounds.
+    tiles: HashMap<(i32, i32), Tile>,
+    free_slots: Vec<u32>,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl TerrainChunks {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let meshes = (0..NUM_LODS)
+            .map(|lod| create_grid_mesh(device, CHUNK_RESOLUTION >> lod))
+            .collect();
+
+        let instances = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("terrain chunk instances"),
+            size: (std::mem::size_of::<TerrainChunk>() as u32 * VIEW_CHUNKS * VIEW_CHUNKS) as u64,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let tile_atlas = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("terrain chunk tile atlas"),
+            size: wgpu::Extent3d {
+                width: TILE_SIZE * ATLAS_TILES_PER_SIDE,
+                height: TILE_SIZE * ATLAS_TILES_PER_SIDE,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        Self {
+            meshes,
+            instances,
+            lod_ranges: Default::default(),
+            tile_atlas_view: tile_atlas.create_view(&wgpu::TextureViewDescriptor::default()),
+            tile_atlas,
+            tiles: HashMap::new(),
+            free_slots: all_slots(),
+            bind_group: None,
+        }
+    }
+
+    pub fn set_height_map(
+        &mut self,
+        device: &wgpu::Device,
+        resources: &RenderResources,
+        height_map: &HeightMap,
+    ) {
+        // The streamed tiles were sampled from the old terrain.
+        self.tiles.clear();
+        self.free_slots = all_slots();
+
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("terrain chunks bind group"),
+            layout: &resources.terrain_chunks_bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&height_map.texture),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&height_map.normals),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&height_map.uvs),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&height_map.tangents),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.tile_atlas_view),
+                },
+            ],
+        }));
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.bind_group
+            .as_ref()
+            .expect("The height map needs to be set before rendering terrain chunks")
+    }
+
+    /// Move the chunks along with the camera, stream in the heights for the ones
+    /// outside the scene bounds and pick the level of detail for each chunk from
+    /// its distance to the camera.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera_position: Vec3,
+        scene_bounds: primitives::SceneBounds,
+        heights: &dyn HeightSource,
+    ) {
+        let chunk_size = (scene_bounds.max - scene_bounds.min) / CHUNKS_PER_SIDE as f32;
+        let mid_height = (scene_bounds.min_height + scene_bounds.max_height) / 2.0;
+
+        // Chunks are counted from the corner of the scene bounds, so that the
+        // ones inside it line up with its edges.
+        let camera_chunk =
+            (Vec2::new(camera_position.x, camera_position.z) - scene_bounds.min) / chunk_size;
+        let first_x = camera_chunk.x.floor() as i32 - VIEW_CHUNKS as i32 / 2;
+        let first_z = camera_chunk.y.floor() as i32 - VIEW_CHUNKS as i32 / 2;
+
+        let chunk_at = |x: u32, z: u32| (first_x + x as i32, first_z + z as i32);
+        let origin =
+            |(x, z): (i32, i32)| scene_bounds.min + Vec2::new(x as f32, z as f32) * chunk_size;
+        let in_scene = |(x, z): (i32, i32)| {
+            x >= 0 && z >= 0 && x < CHUNKS_PER_SIDE as i32 && z < CHUNKS_PER_SIDE as i32
+        };
+
+        let mut lods = [[0; VIEW_CHUNKS as usize]; VIEW_CHUNKS as usize];
+
+        for z in 0..VIEW_CHUNKS {
+            for x in 0..VIEW_CHUNKS {
+                let center = origin(chunk_at(x, z)) + chunk_size / 2.0;
+                let distance = (Vec3::new(center.x, mid_height, center.y) - camera_position).mag();
+
+                let relative_distance = distance / (chunk_size.component_max() * LOD_DISTANCE);
+                let lod = relative_distance.max(1.0).log2().floor() as usize;
+
+                lods[z as usize][x as usize] = lod.min(NUM_LODS - 1) as u32;
+            }
+        }
+
+        // Tiles that have left the view make room for the ones coming into it.
+        let free_slots = &mut self.free_slots;
+        self.tiles.retain(|&(x, z), tile| {
+            let in_view = (first_x..first_x + VIEW_CHUNKS as i32).contains(&x)
+                && (first_z..first_z + VIEW_CHUNKS as i32).contains(&z);
+
+            if !in_view {
+                free_slots.push(tile.slot);
+            }
+
+            in_view
+        });
+
+        let coarsest_lod = NUM_LODS as u32 - 1;
+        let mut refinements = Vec::new();
+
+        for z in 0..VIEW_CHUNKS {
+            for x in 0..VIEW_CHUNKS {
+                let chunk = chunk_at(x, z);
+
+                if in_scene(chunk) {
+                    continue;
+                }
+
+                let tile_lod = match self.tiles.get(&chunk) {
+                    Some(tile) => tile.lod,
+                    None => {
+                        // The coarsest tiles are cheap, so stream them in
+                        // straight away to avoid holes in the terrain.
+                        let slot = self.free_slots.pop().expect("The tile atlas is full");
+                        let tile = Tile {
+                            slot,
+                            lod: coarsest_lod,
+                        };
+                        self.stream_tile(queue, heights, chunk, origin(chunk), chunk_size, tile);
+                        coarsest_lod
+                    }
+                };
+
+                let lod = lods[z as usize][x as usize];
+
+                if lod < tile_lod {
+                    refinements.push((lod, chunk));
+                }
+            }
+        }
+
+        // Closer chunks want finer tiles, so those go first.
+        refinements.sort_by_key(|&(lod, _)| lod);
+
+        let mut budget = STREAMING_BUDGET;
+
+        for (lod, chunk) in refinements {
+            let samples = tile_side(lod) * tile_side(lod);
+
+            if samples > budget {
+                break;
+            }
+
+            budget -= samples;
+
+            let slot = self.tiles[&chunk].slot;
+            let tile = Tile { slot, lod };
+            self.stream_tile(queue, heights, chunk, origin(chunk), chunk_size, tile);
+        }
+
+        // Chunks that are still waiting for a finer tile are drawn at the level
+        // of the one they have, so that every vertex lands on a streamed height.
+        for z in 0..VIEW_CHUNKS {
+            for x in 0..VIEW_CHUNKS {
+                if let Some(tile) = self.tiles.get(&chunk_at(x, z)) {
+                    lods[z as usize][x as usize] = lods[z as usize][x as usize].max(tile.lod);
+                }
+            }
+        }
+
+        // Chunks along the edges of the view use their own level of detail for
+        // the sides without neighbours.
+        let lod_at = |x: i32, z: i32, fallback: u32| {
+            if x < 0 || z < 0 || x >= VIEW_CHUNKS as i32 || z >= VIEW_CHUNKS as i32 {
+                fallback
+            } else {
+                lods[z as usize][x as usize]
+            }
+        };
+
+        let mut chunks = Vec::with_capacity((VIEW_CHUNKS * VIEW_CHUNKS) as usize);
+
+        // Group the chunks by level of detail, so that each level is one draw.
+        for lod in 0..NUM_LODS {
+            let start = chunks.len() as u32;
+
+            for z in 0..VIEW_CHUNKS {
+                for x in 0..VIEW_CHUNKS {
+                    let chunk_lod = lods[z as usize][x as usize];
+
+                    if chunk_lod != lod as u32 {
+                        continue;
+                    }
+
+                    let chunk = chunk_at(x, z);
+                    let tile = self.tiles.get(&chunk);
+                    let (x, z) = (x as i32, z as i32);
+
+                    chunks.push(TerrainChunk {
+                        origin: origin(chunk),
+                        size: chunk_size,
+                        lod: chunk_lod,
+                        neighbour_lods: [
+                            lod_at(x - 1, z, chunk_lod),
+                            lod_at(x + 1, z, chunk_lod),
+                            lod_at(x, z - 1, chunk_lod),
+                            lod_at(x, z + 1, chunk_lod),
+                        ],
+                        tile: tile.map_or([0; 2], |tile| slot_texel(tile.slot)),
+                        tile_lod: tile.map_or(chunk_lod, |tile| tile.lod),
+                    });
+                }
+            }
+
+            self.lod_ranges[lod] = start..chunks.len() as u32;
+        }
+
+        queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&chunks));
+    }
+
+    fn stream_tile(
+        &mut self,
+        queue: &wgpu::Queue,
+        heights: &dyn HeightSource,
+        chunk: (i32, i32),
+        origin: Vec2,
+        chunk_size: Vec2,
+        tile: Tile,
+    ) {
+        let side = tile_side(tile.lod);
+        let [x, y] = slot_texel(tile.slot);
+
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.tile_atlas,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+            },
+            bytemuck::cast_slice(&tile_heights(heights, origin, chunk_size, tile.lod)),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: side * 4,
+                rows_per_image: 0,
+            },
+            wgpu::Extent3d {
+                width: side,
+                height: side,
+                depth: 1,
+            },
+        );
+
+        self.tiles.insert(chunk, tile);
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(1, self.instances.slice(..));
+
+        for (mesh, range) in self.meshes.iter().zip(self.lod_ranges.iter()) {
+            if range.start == range.end {
+                continue;
+            }
+
+            render_pass.set_vertex_buffer(0, mesh.vertices.slice(..));
+            render_pass.set_index_buffer(mesh.indices.slice(..), INDEX_FORMAT);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, range.clone());
+        }
+    }
+}
+
+fn all_slots() -> Vec<u32> {
+    (0..ATLAS_TILES_PER_SIDE * ATLAS_TILES_PER_SIDE)
+        .rev()
+        .collect()
+}
+
+/// The corner of a slot in the tile atlas.
+fn slot_texel(slot: u32) -> [u32; 2] {
+    [
+        slot % ATLAS_TILES_PER_SIDE * TILE_SIZE,
+        slot / ATLAS_TILES_PER_SIDE * TILE_SIZE,
+    ]
+}
+
+/// The number of heights along each side of a tile at a level of detail.
+fn tile_side(lod: u32) -> u32 {
+    (CHUNK_RESOLUTION >> lod) + 3
+}
+
+/// Sample the heights at each vertex of a chunk for a level of detail, along
+/// with a border of one height around them, row by row.
+fn tile_heights(heights: &dyn HeightSource, origin: Vec2, chunk_size: Vec2, lod: u32) -> Vec<f32> {
+    let side = tile_side(lod);
+    let spacing = chunk_size / (CHUNK_RESOLUTION >> lod) as f32;
+
+    (0..side * side)
+        .map(|i| {
+            let vertex = Vec2::new((i % side) as f32, (i / side) as f32) - Vec2::one();
+            heights.height(origin + vertex * spacing)
+        })
+        .collect()
+}
+
+/// A flat grid covering 0 to 1 on each axis.
+fn create_grid_mesh(device: &wgpu::Device, quads_per_side: u32) -> GridMesh {
+    let vertices_per_side = quads_per_side + 1;
+
+    let mut vertices = Vec::with_capacity((vertices_per_side * vertices_per_side) as usize);
+
+    for z in 0..vertices_per_side {
+        for x in 0..vertices_per_side {
+            vertices.push(Vec2::new(x as f32, z as f32) / quads_per_side as f32);
+        }
+    }
+
+    let mut indices = Vec::with_capacity((quads_per_side * quads_per_side * 6) as usize);
+
+    for z in 0..quads_per_side {
+        for x in 0..quads_per_side {
+            let index = |x, z| (z * vertices_per_side + x) as u16;
+
+            let top_left = index(x, z);
+            let top_right = index(x + 1, z);
+            let bottom_left = index(x, z + 1);
+            let bottom_right = index(x + 1, z + 1);
+
+            indices.extend_from_slice(&[
+                top_left,
+                bottom_left,
+                top_right,
+                top_right,
+                bottom_left,
+                bottom_right,
+            ]);
+        }
+    }
+
+    GridMesh {
+        vertices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("terrain chunk vertices"),
+            usage: wgpu::BufferUsage::VERTEX,
+            contents: bytemuck::cast_slice(&vertices),
+        }),
+        indices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("terrain chunk indices"),
+            usage: wgpu::BufferUsage::INDEX,
+            contents: bytemuck::cast_slice(&indices),
+        }),
+        num_indices: indices.len() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Slope;
+
+    impl HeightSource for Slope {
+        fn height(&self, position: Vec2) -> f32 {
+            position.x * 2.0 + position.y * 3.0
+        }
+    }
+
+    #[test]
+    fn tiles_have_a_height_for_each_vertex_inside_the_border() {
+        let origin = Vec2::new(-1.0, 2.0);
+        let chunk_size = Vec2::new(0.25, 0.5);
+
+        for lod in 0..NUM_LODS as u32 {
+            let side = tile_side(lod);
+            let steps = CHUNK_RESOLUTION >> lod;
+            let tile = tile_heights(&Slope, origin, chunk_size, lod);
+
+            assert_eq!(tile.len() as u32, side * side);
+
+            let height = |x: u32, z: u32| tile[((z + 1) * side + x + 1) as usize];
+
+            for &(x, z) in &[
+                (0, 0),
+                (steps, 0),
+                (0, steps),
+                (steps, steps),
+                (steps / 2, 1),
+            ] {
+                let vertex = origin + Vec2::new(x as f32, z as f32) / steps as f32 * chunk_size;
+                assert!((height(x, z) - Slope.height(vertex)).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn slots_fit_inside_the_atlas() {
+        let last = ATLAS_TILES_PER_SIDE * ATLAS_TILES_PER_SIDE - 1;
+        let [x, y] = slot_texel(last);
+
+        assert_eq!(all_slots().len() as u32, VIEW_CHUNKS * VIEW_CHUNKS);
+        assert_eq!(x + TILE_SIZE, TILE_SIZE * ATLAS_TILES_PER_SIDE);
+        assert_eq!(y + TILE_SIZE, TILE_SIZE * ATLAS_TILES_PER_SIDE);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::model_loading::Terrain;
+use primitives::Vertex;
+use std::f32::consts::PI;
+use ultraviolet::{Vec2, Vec3, Vec4};
+
+/// The terrain covers x and z from -HALF_SIZE to HALF_SIZE, the same as `models/dune.glb`.
+const HALF_SIZE: f32 = 2.0;
+/// The number of vertices along each side. 256 * 256 is the most that u16 indices can address.
+const GRID_SIZE: u32 = 256;
+const NORMAL_MAP_SIZE: u32 = 1024;
+const DETAIL_MAP_SIZE: u32 = 512;
+/// The number of noise cells across the tiling detail map.
+const DETAIL_MAP_PERIOD: i32 = 32;
+const DETAIL_MAP_DEPTH: f32 = 0.003;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DuneStyle {
+    /// Crescent-shaped dunes with their horns pointing downwind.
+    Barchan,
+    /// Long, sharp ridges that run parallel to the wind.
+    Seif,
+    /// Pyramid-shaped dunes with arms radiating out from a peak.
+    Star,
+}
+
+impl DuneStyle {
+    pub fn iter() -> impl Iterator<Item = Self> {
+        [Self::Barchan, Self::Seif, Self::Star].iter().cloned()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TerrainParams {
+    /// The angle around the y axis that the prevailing wind blows towards, in radians.
+    pub wind_direction: f32,
+    /// The distance between neighbouring dunes.
+    pub wavelength: f32,
+    /// The height of the tallest dunes.
+    pub height: f32,
+    pub style: DuneStyle,
+    pub seed: u32,
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        Self {
+            wind_direction: PI / 4.0,
+            wavelength: 0.8,
+            height: 0.25,
+            style: DuneStyle::Barchan,
+            seed: 0,
+        }
+    }
+}
+
+/// Generate a terrain mesh along with the normal and detail maps for it.
+pub fn generate(params: TerrainParams) -> Terrain {
+    let heights = DuneHeights::new(params);
+
+    let cell_size = HALF_SIZE * 2.0 / (GRID_SIZE - 1) as f32;
+
+    let mut vertices = Vec::with_capacity((GRID_SIZE * GRID_SIZE) as usize);
+
+    for z in 0..GRID_SIZE {
+        for x in 0..GRID_SIZE {
+            let uv = Vec2::new(x as f32, z as f32) / (GRID_SIZE - 1) as f32;
+            let position = (uv * 2.0 - Vec2::one()) * HALF_SIZE;
+
+            let height = heights.height(position);
+            let gradient = heights.gradient(position, cell_size);
+
+            vertices.push(Vertex {
+                position: Vec3::new(position.x, height, position.y),
+                normal: Vec3::new(-gradient.x, 1.0, -gradient.y).normalized(),
+                uv,
+                tangent: Vec4::new(1.0, gradient.x, 0.0, 0.0).normalized() + Vec4::unit_w(),
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity(((GRID_SIZE - 1) * (GRID_SIZE - 1) * 6) as usize);
+
+    for z in 0..GRID_SIZE - 1 {
+        for x in 0..GRID_SIZE - 1 {
+            let index = |x, z| (z * GRID_SIZE + x) as u16;
+
+            let top_left = index(x, z);
+            let top_right = index(x + 1, z);
+            let bottom_left = index(x, z + 1);
+            let bottom_right = index(x + 1, z + 1);
+
+            indices.extend_from_slice(&[
+                top_left,
+                bottom_left,
+                top_right,
+                top_right,
+                bottom_left,
+                bottom_right,
+            ]);
+        }
+    }
+
+    let normals = normal_map(NORMAL_MAP_SIZE, |uv| {
+        let position = (uv * 2.0 - Vec2::one()) * HALF_SIZE;
+        heights.ripple_gradient(position, HALF_SIZE * 2.0 / NORMAL_MAP_SIZE as f32)
+    });
+
+    let details = normal_map(DETAIL_MAP_SIZE, |uv| {
+        // The noise wraps around so that the detail map can be tiled.
+        let noise = |uv: Vec2| {
+            value_noise(
+                uv * DETAIL_MAP_PERIOD as f32,
+                params.seed,
+                Some(DETAIL_MAP_PERIOD),
+            ) * DETAIL_MAP_DEPTH
+        };
+
+        let step = 1.0 / DETAIL_MAP_SIZE as f32;
+        let x = Vec2::new(step, 0.0);
+        let y = Vec2::new(0.0, step);
+
+        Vec2::new(noise(uv + x) - noise(uv - x), noise(uv + y) - noise(uv - y)) / (step * 2.0)
+    });
+
+    Terrain {
+        vertices,
+        indices,
+        normals,
+        details,
+        layer_details: Default::default(),
+        splat_map: None,
+        routes: Vec::new(),
+    }
+}
+
+/// The height of the dunes at any position, which carries on past the generated mesh.
+pub struct DuneHeights {
+    params: TerrainParams,
+    wind: Vec2,
+    across_wind: Vec2,
+}
+
+impl DuneHeights {
+    pub fn new(params: TerrainParams) -> Self {
+        let wind = Vec2::new(params.wind_direction.cos(), params.wind_direction.sin());
+
+        Self {
+            params,
+            wind,
+            across_wind: Vec2::new(-wind.y, wind.x),
+        }
+    }
+
+    /// Between 0 and the height in the params.
+    pub fn height(&self, position: Vec2) -> f32 {
+        let seed = self.params.seed;
+        let wavelength = self.params.wavelength;
+
+        // Distort the dunes slightly so that they don't look too regular.
+        let warp = fbm(position * 1.5, seed) * 0.5;
+
+        let along = position.dot(self.wind) / wavelength + warp;
+        let across = position.dot(self.across_wind) / wavelength + warp;
+
+        let shape = match self.params.style {
+            DuneStyle::Barchan => barchan(along, across, seed),
+            DuneStyle::Seif => ridge(across).powf(1.5),
+            DuneStyle::Star => star(along, across, seed),
+        };
+
+        let variation = 0.75 + 0.25 * fbm(position * 0.5, seed.wrapping_add(1));
+        let bumps = 0.5 + 0.5 * fbm(position * 3.0, seed.wrapping_add(2));
+
+        // Both the dunes and the bumps are between 0 and 1, so weight them to stay under 1.
+        (shape * variation * 0.9 + bumps * 0.1) * self.params.height
+    }
+
+    fn gradient(&self, position: Vec2, step: f32) -> Vec2 {
+        let x = Vec2::new(step, 0.0);
+        let z = Vec2::new(0.0, step);
+
+        Vec2::new(
+            self.height(position + x) - self.height(position - x),
+            self.height(position + z) - self.height(position - z),
+        ) / (step * 2.0)
+    }
+
+    /// The gradient of the small ripples that the wind leaves in the sand. These
+    /// are too small for the mesh, so they go into the normal map instead.
+    fn ripple_gradient(&self, position: Vec2, step: f32) -> Vec2 {
+        let ripple = |position: Vec2| {
+            let phase =
+                position.dot(self.wind) * 35.0 + fbm(position * 4.0, self.params.seed) * 2.0;
+            (phase * PI * 2.0).sin() * 0.002
+        };
+
+        let x = Vec2::new(step, 0.0);
+        let z = Vec2::new(0.0, step);
+
+        Vec2::new(
+            ripple(position + x) - ripple(position - x),
+            ripple(position + z) - ripple(position - z),
+        ) / (step * 2.0)
+    }
+}
+
+/// A triangle wave between 0 and 1 that peaks halfway between whole numbers.
+fn ridge(x: f32) -> f32 {
+    1.0 - (x.rem_euclid(1.0) * 2.0 - 1.0).abs()
+}
+
+/// Crescents with a gentle windward slope and a steep slip face, in rows that
+/// are staggered across the wind.
+fn barchan(along: f32, across: f32, seed: u32) -> f32 {
+    // Each crescent is two wavelengths wide.
+    let across = across * 0.5;
+    let row = across.floor();
+    let across_fract = across - row;
+
+    // Bend the crest downwind towards the horns.
+    let horn = (across_fract * 2.0 - 1.0).powi(2) * 0.4;
+    let stagger = hash(row as i32, 0, seed);
+
+    let t = (along - horn + stagger).rem_euclid(1.0);
+
+    let profile = if t < 0.75 {
+        smoothstep(t / 0.75)
+    } else {
+        1.0 - (t - 0.75) / 0.25
+    };
+
+    profile * (across_fract * PI).sin()
+}
+
+/// Peaks on a jittered grid with three arms radiating out from each one.
+fn star(along: f32, across: f32, seed: u32) -> f32 {
+    let position = Vec2::new(along, across) * 0.5;
+    let cell = Vec2::new(position.x.floor(), position.y.floor());
+
+    let jitter = Vec2::new(
+        hash(cell.x as i32, cell.y as i32, seed),
+        hash(cell.x as i32, cell.y as i32, seed.wrapping_add(1)),
+    );
+    let peak = cell + Vec2::broadcast(0.25) + jitter * 0.5;
+    let offset = position - peak;
+
+    let angle = offset.y.atan2(offset.x);
+    let arms = (angle * 1.5).cos().abs().powi(8);
+
+    let radius = 0.5 * (0.5 + 0.5 * arms);
+
+    smoothstep((1.0 - offset.mag() / radius).max(0.0))
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let x = x.max(0.0).min(1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// A pseudo-random number between 0 and 1 for a lattice point.
+fn hash(x: i32, y: i32, seed: u32) -> f32 {
+    let mut hash = (x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((y as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add(seed.wrapping_mul(0xcb1a_b31f));
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1_e995);
+    hash ^= hash >> 15;
+
+    hash as f32 / u32::MAX as f32
+}
+
+fn value_noise(position: Vec2, seed: u32, period: Option<i32>) -> f32 {
+    let x = position.x.floor();
+    let y = position.y.floor();
+
+    let lattice = |offset_x: i32, offset_y: i32| {
+        let mut lattice_x = x as i32 + offset_x;
+        let mut lattice_y = y as i32 + offset_y;
+
+        if let Some(period) = period {
+            lattice_x = lattice_x.rem_euclid(period);
+            lattice_y = lattice_y.rem_euclid(period);
+        }
+
+        hash(lattice_x, lattice_y, seed)
+    };
+
+    let fract_x = smoothstep(position.x - x);
+    let fract_y = smoothstep(position.y - y);
+
+    let top = lattice(0, 0) + (lattice(1, 0) - lattice(0, 0)) * fract_x;
+    let bottom = lattice(0, 1) + (lattice(1, 1) - lattice(0, 1)) * fract_x;
+
+    (top + (bottom - top) * fract_y) * 2.0 - 1.0
+}
+
+/// Fractal value noise between -1 and 1.
+fn fbm(position: Vec2, seed: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    let mut position = position;
+
+    for octave in 0..4 {
+        sum += value_noise(position, seed.wrapping_add(octave), None) * amplitude;
+        position *= 2.0;
+        amplitude *= 0.5;
+    }
+
+    sum
+}
+
+/// Encode a tangent space normal map from the gradients of a height function
+/// over the uv space.
+fn normal_map(size: u32, gradient: impl Fn(Vec2) -> Vec2) -> image::RgbaImage {
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        let uv = (Vec2::new(x as f32, y as f32) + Vec2::broadcast(0.5)) / size as f32;
+        let gradient = gradient(uv);
+
+        // The tangent runs along +x and the bitangent along -z.
+        let normal = Vec3::new(-gradient.x, gradient.y, 1.0).normalized();
+        let encoded = (normal * 0.5 + Vec3::broadcast(0.5)) * 255.0;
+
+        image::Rgba([encoded.x as u8, encoded.y as u8, encoded.z as u8, 255])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_params_give_identical_vertices() {
+        let params = TerrainParams {
+            style: DuneStyle::Star,
+            seed: 7,
+            ..Default::default()
+        };
+
+        let first = generate(params);
+        let second = generate(params);
+
+        let bytes = |terrain: &Terrain| bytemuck::cast_slice::<_, u8>(&terrain.vertices).to_vec();
+        assert_eq!(bytes(&first), bytes(&second));
+        assert_eq!(first.indices, second.indices);
+    }
+
+    #[test]
+    fn heights_stay_within_the_dune_height() {
+        for style in DuneStyle::iter() {
+            for &seed in &[0, 1, 500] {
+                let params = TerrainParams {
+                    style,
+                    seed,
+                    ..Default::default()
+                };
+
+                let heights = DuneHeights::new(params);
+
+                // The same positions as the vertices of the mesh.
+                for i in 0..GRID_SIZE * GRID_SIZE {
+                    let uv = Vec2::new((i % GRID_SIZE) as f32, (i / GRID_SIZE) as f32)
+                        / (GRID_SIZE - 1) as f32;
+                    let height = heights.height((uv * 2.0 - Vec2::one()) * HALF_SIZE);
+
+                    assert!(
+                        (0.0..=params.height).contains(&height),
+                        "{:?} dunes with seed {} have a height of {}",
+                        style,
+                        seed,
+                        height
+                    );
+                }
+            }
+        }
+    }
+}
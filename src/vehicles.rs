@@ -0,0 +1,733 @@
+use crate::height_field::HeightField;
+use crate::model_loading::{Scene, VehicleModel};
+use crate::resource_creation::{
+    create_land_craft, create_land_craft_bind_group, create_particle_bind_group,
+    create_ship_bind_group, create_ships, create_vehicle_grid, ParticleBuffers, VehicleBuffers,
+    MAX_GRID_SIZE,
+};
+use crate::resources_and_pipelines::{Pipelines, RenderResources};
+use crate::routes::Routes;
+use crate::{dispatch_count, INDEX_FORMAT};
+use cascaded_shadow_maps::BoundingBox;
+use primitives::MAX_SPAWN_OFFSETS;
+use serde::Deserialize;
+use std::borrow::Cow;
+use ultraviolet::{Vec2, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+/// The files that vehicle types can use in the browser, where they can't be
+/// read from disk.
+#[cfg(feature = "wasm")]
+const FILES: &[(&str, &[u8])] = &[
+    ("vehicles.json", include_bytes!("../vehicles.json")),
+    ("models/ship.glb", include_bytes!("../models/ship.glb")),
+    (
+        "models/landcraft.glb",
+        include_bytes!("../models/landcraft.glb"),
+    ),
+];
+
+/// A type of vehicle, as described in `vehicles.json`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct VehicleType {
+    /// Shown in the ui, such as "Ships".
+    pub name: String,
+    /// The path of a glTF binary, relative to the working directory. In the
+    /// browser this needs to be one of the built in `FILES`.
+    pub model: String,
+    /// Applied to the model and the emitter offsets.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    pub movement: Movement,
+    pub spawn: SpawnRules,
+    #[serde(default)]
+    pub emitters: Vec<Emitter>,
+    /// Scroll part of the texture along, such as for treads.
+    #[serde(default)]
+    pub uv_scroll: Option<UvScroll>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Which compute shaders move the vehicles. Vehicles only flock with or avoid
+/// others of the same type.
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum Movement {
+    /// Fly over the terrain, as in `ship/movement.comp`. Each vehicle turns at
+    /// a random speed between `turn_speeds` when they aren't flocking, and
+    /// bobs up and down by `bob_amplitude` around its clearance.
+    Flying {
+        turn_speeds: [f32; 2],
+        bob_amplitude: f32,
+    },
+    /// Drive around the routes, leaving tracks in the sand, as in
+    /// `land_craft/movement.comp`. Each vehicle drives at a random speed
+    /// between `speeds`.
+    Driving { speeds: [f32; 2] },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnRules {
+    /// How many vehicles there are to start with, at least 1.
+    pub count: u32,
+    /// The most vehicles that can be picked in the ui.
+    pub max_count: u32,
+    #[serde(default)]
+    pub placement: Placement,
+}
+
+/// Where vehicles can be spawned. Each vehicle is spawned at the first of a few
+/// random spots that follows these rules, or at the last spot tried if none do.
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Placement {
+    /// The steepest ground to spawn over, in degrees.
+    #[serde(default = "default_max_slope")]
+    pub max_slope: f32,
+    /// The range of ground heights to spawn over, from 0 at the lowest point of
+    /// the terrain to 1 at the highest.
+    #[serde(default = "default_height_range")]
+    pub height_range: [f32; 2],
+}
+
+fn default_max_slope() -> f32 {
+    90.0
+}
+
+fn default_height_range() -> [f32; 2] {
+    [0.0, 1.0]
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            max_slope: default_max_slope(),
+            height_range: default_height_range(),
+        }
+    }
+}
+
+impl Placement {
+    /// Whether the ground at a position on the xz plane follows these rules.
+    pub fn allows(
+        &self,
+        position: Vec2,
+        height_field: &HeightField,
+        scene_bounds: primitives::SceneBounds,
+    ) -> bool {
+        let slope = height_field.slope_at(position.x, position.y).to_degrees();
+
+        let height = (height_field.height_at(position.x, position.y) - scene_bounds.min_height)
+            / (scene_bounds.max_height - scene_bounds.min_height).max(f32::EPSILON);
+
+        slope <= self.max_slope && height >= self.height_range[0] && height <= self.height_range[1]
+    }
+}
+
+/// Particles that are left behind each vehicle, such as exhaust.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Emitter {
+    pub name: String,
+    /// Where the particles are spawned from in the model, with one particle
+    /// spawned from each offset at a time.
+    pub offsets: Vec<[f32; 3]>,
+    pub colour: EmitterColour,
+    /// The half size that the particles grow to.
+    pub size: f32,
+    /// How many particles are kept around for each vehicle, which decides how
+    /// long they last.
+    pub particles_per_vehicle: u32,
+    /// In seconds. Particles are spawned every step if this is 0.
+    #[serde(default)]
+    pub cooldown: f32,
+    /// The speed of the particles along the vehicle's up axis.
+    #[serde(default)]
+    pub up_speed: f32,
+    /// The speed of the particles in a random direction.
+    #[serde(default)]
+    pub random_speed: f32,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+pub enum EmitterColour {
+    Rgb([f32; 3]),
+    /// The albedo of the first terrain layer, multiplied by this.
+    Sand(f32),
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UvScroll {
+    /// UVs with a v above this are scrolled.
+    pub threshold: f32,
+    /// In UVs per second.
+    pub speed: f32,
+}
+
+impl VehicleType {
+    /// Load the vehicle types from `vehicles.json`.
+    pub fn load_all() -> anyhow::Result<Vec<Self>> {
+        let vehicle_types: Vec<Self> = serde_json::from_slice(&read_file("vehicles.json")?)
+            .map_err(|error| anyhow::anyhow!("Invalid vehicles.json: {}", error))?;
+
+        for vehicle_type in &vehicle_types {
+            vehicle_type.validate().map_err(|error| {
+                anyhow::anyhow!("Invalid vehicle type '{}': {}", vehicle_type.name, error)
+            })?;
+        }
+
+        Ok(vehicle_types)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        // Empty buffers can't be bound, so there's always at least one vehicle.
+        if self.spawn.count == 0 || self.spawn.count > self.spawn.max_count {
+            return Err(anyhow::anyhow!(
+                "The count needs to be between 1 and the max count"
+            ));
+        }
+
+        let placement = &self.spawn.placement;
+
+        if !(0.0..=90.0).contains(&placement.max_slope) {
+            return Err(anyhow::anyhow!(
+                "The max slope needs to be between 0 and 90 degrees"
+            ));
+        }
+
+        if placement.height_range[0] > placement.height_range[1] {
+            return Err(anyhow::anyhow!(
+                "The height range needs to go from low to high"
+            ));
+        }
+
+        for emitter in &self.emitters {
+            if emitter.offsets.is_empty() || emitter.offsets.len() > MAX_SPAWN_OFFSETS {
+                return Err(anyhow::anyhow!(
+                    "Emitter '{}' needs between 1 and {} offsets",
+                    emitter.name,
+                    MAX_SPAWN_OFFSETS
+                ));
+            }
+
+            if (emitter.particles_per_vehicle as usize) < emitter.offsets.len() {
+                return Err(anyhow::anyhow!(
+                    "Emitter '{}' needs at least as many particles per vehicle as offsets",
+                    emitter.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+fn read_file(path: &str) -> anyhow::Result<Cow<'static, [u8]>> {
+    std::fs::read(path)
+        .map(Cow::Owned)
+        .map_err(|error| anyhow::anyhow!("Failed to read '{}': {}", path, error))
+}
+
+#[cfg(feature = "wasm")]
+fn read_file(path: &str) -> anyhow::Result<Cow<'static, [u8]>> {
+    FILES
+        .iter()
+        .find(|(file, _)| *file == path)
+        .map(|(_, bytes)| Cow::Borrowed(*bytes))
+        .ok_or_else(|| anyhow::anyhow!("'{}' isn't one of the built in files", path))
+}
+
+/// What the vehicles are spawned into. They need to be spawned again whenever
+/// the terrain changes, but a newly baked height map only needs
+/// `Vehicles::set_height_map`.
+pub struct SpawnContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub resources: &'a RenderResources,
+    pub height_field: &'a HeightField,
+    pub height_map: &'a wgpu::TextureView,
+    pub height_map_normals: &'a wgpu::TextureView,
+    pub settings: &'a primitives::Settings,
+    pub scene_bounds: primitives::SceneBounds,
+    pub sand_colour: Vec3,
+    pub routes: &'a Routes,
+    pub collisions_buffer: &'a wgpu::Buffer,
+}
+
+/// All the vehicles of one type.
+pub struct Vehicles {
+    pub vehicle_type: VehicleType,
+    pub model: VehicleModel,
+    /// How many vehicles there should be the next time they're spawned.
+    pub num: u32,
+    pub render: bool,
+    pub render_shadows: bool,
+    type_info_buffer: wgpu::Buffer,
+    spawned: Spawned,
+}
+
+struct Spawned {
+    movement_bind_group: wgpu::BindGroup,
+    render_bind_group: wgpu::BindGroup,
+    grid_bind_group: wgpu::BindGroup,
+    buffers: VehicleBuffers,
+    particles: Vec<Particles>,
+}
+
+struct Particles {
+    bind_group: wgpu::BindGroup,
+    buffers: ParticleBuffers,
+    num: u32,
+}
+
+impl Vehicles {
+    pub fn new(
+        vehicle_type: VehicleType,
+        context: &SpawnContext,
+        rng: &mut rand::rngs::StdRng,
+    ) -> anyhow::Result<Self> {
+        let model = VehicleModel::load(
+            &read_file(&vehicle_type.model)?,
+            vehicle_type.scale,
+            context.device,
+            context.queue,
+            context.resources,
+        )?;
+
+        let (uv_scroll_threshold, uv_scroll_speed) = match vehicle_type.uv_scroll {
+            Some(uv_scroll) => (uv_scroll.threshold, uv_scroll.speed),
+            None => (f32::MAX, 0.0),
+        };
+
+        let type_info = primitives::VehicleTypeInfo {
+            uv_scroll_threshold,
+            uv_scroll_speed,
+            bob_amplitude: match vehicle_type.movement {
+                Movement::Flying { bob_amplitude, .. } => bob_amplitude,
+                Movement::Driving { .. } => 0.0,
+            },
+            ..Default::default()
+        };
+
+        let type_info_buffer =
+            context
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{} type info buffer", vehicle_type.name)),
+                    usage: wgpu::BufferUsage::UNIFORM,
+                    contents: bytemuck::bytes_of(&type_info),
+                });
+
+        let num = vehicle_type.spawn.count;
+        let spawned = spawn(&vehicle_type, num, None, &type_info_buffer, context, rng);
+
+        Ok(Self {
+            vehicle_type,
+            model,
+            num,
+            render: true,
+            render_shadows: true,
+            type_info_buffer,
+            spawned,
+        })
+    }
+
+    /// Spawn `num` vehicles. If `keep_existing` is set then the vehicles that
+    /// are already around carry on, and only the extra ones are spawned.
+    pub fn respawn(
+        &mut self,
+        keep_existing: bool,
+        context: &SpawnContext,
+        rng: &mut rand::rngs::StdRng,
+    ) {
+        if keep_existing && self.num == self.spawned.buffers.num_vehicles() {
+            return;
+        }
+
+        let previous = if keep_existing {
+            Some(&self.spawned)
+        } else {
+            None
+        };
+
+        self.spawned = spawn(
+            &self.vehicle_type,
+            self.num,
+            previous,
+            &self.type_info_buffer,
+            context,
+            rng,
+        );
+    }
+
+    /// Bind the vehicles to a newly baked height map. They carry on from where
+    /// they were, as the terrain itself hasn't changed.
+    pub fn set_height_map(&mut self, context: &SpawnContext) {
+        self.spawned.movement_bind_group = create_movement_bind_group(
+            &self.vehicle_type,
+            &self.spawned.buffers,
+            &self.type_info_buffer,
+            context,
+        );
+    }
+
+    #[cfg(test)]
+    pub fn buffers(&self) -> &VehicleBuffers {
+        &self.spawned.buffers
+    }
+
+    /// The buffers of particles and their info for an emitter, along with the
+    /// number of particles.
+    #[cfg(test)]
+    pub fn particles(&self, emitter: usize) -> (&wgpu::Buffer, &wgpu::Buffer, u32) {
+        let particles = &self.spawned.particles[emitter];
+        (
+            &particles.buffers.particles,
+            &particles.buffers.info,
+            particles.num,
+        )
+    }
+
+    /// The bind group and number of vehicles to stamp tracks into the sand for.
+    pub fn tracks(&self) -> Option<(&wgpu::BindGroup, u32)> {
+        match self.vehicle_type.movement {
+            Movement::Driving { .. } => Some((
+                &self.spawned.movement_bind_group,
+                self.spawned.buffers.num_vehicles(),
+            )),
+            Movement::Flying { .. } => None,
+        }
+    }
+
+    pub fn move_particles<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        pipelines: &'a Pipelines,
+        bind_group: &'a wgpu::BindGroup,
+    ) {
+        compute_pass.set_pipeline(&pipelines.particles_movement_pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+
+        for particles in &self.spawned.particles {
+            compute_pass.set_bind_group(1, &particles.bind_group, &[]);
+            compute_pass.dispatch(dispatch_count(particles.num, 64), 1, 1);
+        }
+    }
+
+    /// Move the vehicles and then spawn their particles.
+    pub fn step<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        pipelines: &'a Pipelines,
+        bind_group: &'a wgpu::BindGroup,
+        settings: &primitives::Settings,
+    ) {
+        let num_vehicles = self.spawned.buffers.num_vehicles();
+
+        let (sort_into_grid, movement_pipeline) = match self.vehicle_type.movement {
+            Movement::Flying { .. } => (settings.flocking != 0, &pipelines.ship_movement_pipeline),
+            Movement::Driving { .. } => (true, &pipelines.land_craft_movement_pipeline),
+        };
+
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.set_bind_group(2, &self.spawned.grid_bind_group, &[]);
+
+        if sort_into_grid {
+            let num_cells = MAX_GRID_SIZE * MAX_GRID_SIZE;
+
+            compute_pass.set_bind_group(1, &self.spawned.render_bind_group, &[]);
+
+            compute_pass.set_pipeline(&pipelines.vehicle_grid_clear_pipeline);
+            compute_pass.dispatch(dispatch_count(num_cells, 64), 1, 1);
+
+            compute_pass.set_pipeline(&pipelines.vehicle_grid_count_pipeline);
+            compute_pass.dispatch(dispatch_count(num_vehicles, 64), 1, 1);
+
+            // The offsets are summed in a single workgroup.
+            compute_pass.set_pipeline(&pipelines.vehicle_grid_offsets_pipeline);
+            compute_pass.dispatch(1, 1, 1);
+
+            compute_pass.set_pipeline(&pipelines.vehicle_grid_scatter_pipeline);
+            compute_pass.dispatch(dispatch_count(num_vehicles, 64), 1, 1);
+
+            compute_pass.set_pipeline(&pipelines.vehicle_grid_sort_pipeline);
+            compute_pass.dispatch(dispatch_count(num_cells, 64), 1, 1);
+        }
+
+        compute_pass.set_bind_group(1, &self.spawned.movement_bind_group, &[]);
+        compute_pass.set_pipeline(movement_pipeline);
+        compute_pass.dispatch(dispatch_count(num_vehicles, 64), 1, 1);
+
+        compute_pass.set_bind_group(1, &self.spawned.render_bind_group, &[]);
+
+        // Every vehicle spawns from the same info, which is only advanced once
+        // they all have.
+        for particles in &self.spawned.particles {
+            compute_pass.set_bind_group(2, &particles.bind_group, &[]);
+
+            compute_pass.set_pipeline(&pipelines.particles_spawn_pipeline);
+            compute_pass.dispatch(dispatch_count(num_vehicles, 64), 1, 1);
+
+            compute_pass.set_pipeline(&pipelines.particles_advance_pipeline);
+            compute_pass.dispatch(1, 1, 1);
+        }
+    }
+
+    pub fn render_shadows<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a Pipelines,
+        light_projection_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.render_shadows {
+            return;
+        }
+
+        render_pass.set_pipeline(&pipelines.vehicle_shadows_pipeline);
+        render_pass.set_bind_group(0, light_projection_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.spawned.render_bind_group, &[]);
+        self.draw(render_pass);
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a Pipelines,
+        bind_group: &'a wgpu::BindGroup,
+        shadows_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.render {
+            return;
+        }
+
+        render_pass.set_pipeline(&pipelines.vehicle_pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.set_bind_group(1, &self.spawned.render_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.model.texture_bind_group, &[]);
+        render_pass.set_bind_group(3, shadows_bind_group, &[]);
+        self.draw(render_pass);
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.model.vertices.slice(..));
+        render_pass.set_index_buffer(self.model.indices.slice(..), INDEX_FORMAT);
+        render_pass.draw_indexed(
+            0..self.model.num_indices,
+            0,
+            0..self.spawned.buffers.num_vehicles(),
+        );
+    }
+
+    pub fn render_particles<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a Pipelines,
+        bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&pipelines.particles_pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+
+        for particles in &self.spawned.particles {
+            render_pass.set_bind_group(1, &particles.bind_group, &[]);
+            render_pass.draw(0..particles.num * 6, 0..1);
+        }
+    }
+
+    /// The bounds of everywhere that the vehicles can be.
+    pub fn bounds(&self, scene: &Scene, settings: &primitives::Settings) -> BoundingBox {
+        // Vehicles can face any direction, so use the largest distance from their origin.
+        let radius = self
+            .model
+            .bounds
+            .corners()
+            .iter()
+            .map(|corner| corner.mag())
+            .fold(0.0, f32::max);
+
+        match self.vehicle_type.movement {
+            Movement::Flying { bob_amplitude, .. } => {
+                let movement_bounds = settings.ship_movement_bounds + radius;
+                let center = scene.xz_bounds().center();
+
+                // They follow the ground, bobbing a little around their clearance.
+                BoundingBox::new(
+                    Vec3::new(
+                        center.x - movement_bounds,
+                        scene.bounds.min.y + settings.ship_clearance - bob_amplitude - radius,
+                        center.y - movement_bounds,
+                    ),
+                    Vec3::new(
+                        center.x + movement_bounds,
+                        scene.bounds.max.y + settings.ship_clearance + bob_amplitude + radius,
+                        center.y + movement_bounds,
+                    ),
+                )
+            }
+            Movement::Driving { .. } => {
+                BoundingBox::new(scene.bounds.min, scene.bounds.max + Vec3::broadcast(radius))
+            }
+        }
+    }
+}
+
+fn spawn(
+    vehicle_type: &VehicleType,
+    num: u32,
+    previous: Option<&Spawned>,
+    type_info_buffer: &wgpu::Buffer,
+    context: &SpawnContext,
+    rng: &mut rand::rngs::StdRng,
+) -> Spawned {
+    let placement = vehicle_type.spawn.placement;
+    let can_place =
+        |position: Vec2| placement.allows(position, context.height_field, context.scene_bounds);
+
+    let buffers = match vehicle_type.movement {
+        Movement::Flying { turn_speeds, .. } => create_ships(
+            num,
+            previous.map(|previous| &previous.buffers),
+            context.device,
+            context.queue,
+            rng,
+            context.settings,
+            context.scene_bounds,
+            turn_speeds[0]..=turn_speeds[1],
+            can_place,
+        ),
+        Movement::Driving { speeds } => create_land_craft(
+            num,
+            previous.map(|previous| &previous.buffers),
+            context.device,
+            context.queue,
+            rng,
+            context.routes,
+            speeds[0]..=speeds[1],
+            can_place,
+        ),
+    };
+
+    let movement_bind_group =
+        create_movement_bind_group(vehicle_type, &buffers, type_info_buffer, context);
+
+    let render_bind_group = context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} bind group", vehicle_type.name)),
+            layout: &context.resources.vehicle_bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.vehicles().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: type_info_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+    let grid_bind_group = create_vehicle_grid(
+        context.device,
+        context.resources,
+        &vehicle_type.name,
+        num,
+        matches!(vehicle_type.movement, Movement::Flying { .. }),
+    );
+
+    let particles = vehicle_type
+        .emitters
+        .iter()
+        .enumerate()
+        .map(|(i, emitter)| {
+            let num_particles = num * emitter.particles_per_vehicle;
+
+            let (bind_group, particle_buffers) = create_particle_bind_group(
+                context.device,
+                &format!("{} {} particles", vehicle_type.name, emitter.name),
+                num_particles as u64,
+                particles_info(emitter, vehicle_type.scale, context.sand_colour),
+                context.resources,
+            );
+
+            if let Some(previous) = previous {
+                previous.particles[i].buffers.copy_into(
+                    &particle_buffers,
+                    context.device,
+                    context.queue,
+                );
+            }
+
+            Particles {
+                bind_group,
+                buffers: particle_buffers,
+                num: num_particles,
+            }
+        })
+        .collect();
+
+    Spawned {
+        movement_bind_group,
+        render_bind_group,
+        grid_bind_group,
+        buffers,
+        particles,
+    }
+}
+
+fn create_movement_bind_group(
+    vehicle_type: &VehicleType,
+    buffers: &VehicleBuffers,
+    type_info_buffer: &wgpu::Buffer,
+    context: &SpawnContext,
+) -> wgpu::BindGroup {
+    match vehicle_type.movement {
+        Movement::Flying { .. } => create_ship_bind_group(
+            context.device,
+            context.resources,
+            buffers,
+            context.height_map,
+            type_info_buffer,
+        ),
+        Movement::Driving { .. } => create_land_craft_bind_group(
+            context.device,
+            context.resources,
+            buffers,
+            context.height_map,
+            context.height_map_normals,
+            context.collisions_buffer,
+        ),
+    }
+}
+
+fn particles_info(
+    emitter: &Emitter,
+    scale: f32,
+    sand_colour: Vec3,
+) -> primitives::ParticlesBufferInfo {
+    let mut spawn_offsets = [Vec4::zero(); MAX_SPAWN_OFFSETS];
+
+    for (spawn_offset, offset) in spawn_offsets.iter_mut().zip(&emitter.offsets) {
+        *spawn_offset = Vec4::new(offset[0], offset[1], offset[2], 0.0) * scale;
+    }
+
+    primitives::ParticlesBufferInfo {
+        colour: match emitter.colour {
+            EmitterColour::Rgb(colour) => colour.into(),
+            EmitterColour::Sand(brightness) => sand_colour * brightness,
+        },
+        half_size_linear: emitter.size,
+        spawn_cooldown: emitter.cooldown,
+        spawn_offsets,
+        num_spawn_offsets: emitter.offsets.len() as u32,
+        up_speed: emitter.up_speed,
+        random_speed: emitter.random_speed,
+        ..Default::default()
+    }
+}